@@ -1,5 +1,16 @@
-use itertools::Itertools;
 use discord;
+use discord::model::{Message, MessageId};
+
+use chrono::Duration;
+
+use clock::Clock;
+use markdown;
+use markdown::{Line, Span};
+use wrap;
+use mentions;
+use mentions::Names;
+
+use unicode_segmentation::UnicodeSegmentation;
 
 use tui::layout::{Group, Size, Rect, Direction};
 use tui::widgets::{Widget, Item, Block};
@@ -8,6 +19,9 @@ use tui::style::{Color, Modifier, Style};
 
 use std::cmp::{max, min};
 
+/// Messages from the same person this close together share one header
+const GROUP_MINUTES: i64 = 7;
+
 static style: Style = Style {
     fg: Color::Gray,
     bg: Color::Reset,
@@ -17,7 +31,11 @@ static style: Style = Style {
 pub struct ChatWidget<'a>{
     selected: Option<usize>,
     scroll: usize,
+    loading: bool,
     messages: &'a Vec<discord::model::Message>,
+    names: Option<&'a Names>,
+    divider: Option<MessageId>,
+    clock: Clock,
 	 block: Option<Block<'a>>,
 }
 
@@ -27,6 +45,10 @@ impl<'a> ChatWidget<'a> {
             selected: None,
 			block: None,
             scroll: 0,
+            loading: false,
+            names: None,
+            divider: None,
+            clock: Clock::default(),
             messages
         }
     }
@@ -38,6 +60,31 @@ impl<'a> ChatWidget<'a> {
 		self.scroll = scroll;
 		self
 	}
+	/// Highlight the message at this index
+	pub fn select(&mut self, selected: Option<usize>) -> &mut Self {
+		self.selected = selected;
+		self
+	}
+	/// Who and what the ids in mentions stand for
+	pub fn names(&mut self, names: &'a Names) -> &mut Self {
+		self.names = Some(names);
+		self
+	}
+	/// Draw a "new messages" line above the first message after this one
+	pub fn divider(&mut self, last_read: Option<MessageId>) -> &mut Self {
+		self.divider = last_read;
+		self
+	}
+	/// How times and days are shown
+	pub fn clock(&mut self, clock: Clock) -> &mut Self {
+		self.clock = clock;
+		self
+	}
+	/// Show a placeholder row above the oldest message while a page is fetched
+	pub fn loading(&mut self, loading: bool) -> &mut Self {
+		self.loading = loading;
+		self
+	}
 }
 
 impl<'a> Widget for ChatWidget<'a> {
@@ -55,31 +102,178 @@ impl<'a> Widget for ChatWidget<'a> {
         }
 
         self.background(&list_area, buf, Style::default().bg); 
+        let width = list_area.width as usize;
+        let mut top = list_area.top();
+        let mut height = list_area.height as usize;
+        if self.loading {
+            buf.set_stringn(
+                list_area.left(),
+                top,
+                "-- loading older messages --",
+                width,
+                &Style::default().fg(Color::DarkGray),
+            );
+            top += 1;
+            height -= 1;
+        }
+
+        // Lay out messages from the newest visible one upwards until the area is
+        // full, so a long message never pushes the newest one off the bottom
+        let no_names = Names::default();
+        let layout = Layout {
+            width,
+            names: self.names.unwrap_or(&no_names),
+            divider: self.divider,
+            clock: self.clock,
+        };
         let n = self.messages.len();
-        let nm = (list_area.height as usize).checked_sub(2).unwrap_or(0);
-        let left_bound = n.checked_sub(nm+self.scroll).unwrap_or(0);
-        let right_bound = min(n, nm.checked_sub(self.scroll).unwrap_or(0));
-        let msgs = &self.messages[left_bound..right_bound];
-        
-        let mut y: usize = 0;
-
-        msgs.iter().foreach( |msg| {
-            let mut i: usize = 0;
-            let content = format!("{}: {}", &msg.author.name[..], &msg.content[..]);
-            while i < content.len(){
-                let w = min(list_area.width as usize, content.len()-i);
-                //println!("i={}, w={}, len={}, y={}", i, w, content.len(), y);
-                //:
-                buf.set_stringn(
-                    list_area.left(),
-                    list_area.top() + min(y,nm) as u16,
-                    &content[i..i+w],
-                    list_area.width as usize,
-                    &Style::default(),
-                );
-                i += w;
-                y += 1;
+        let end = n.checked_sub(self.scroll).unwrap_or(0);
+        let mut blocks: Vec<Vec<Line>> = vec![];
+        let mut total = 0;
+        for index in (0..end).rev() {
+            let mut rows = layout.rows(self.messages, index);
+            if self.selected == Some(index) {
+                for row in rows.iter_mut() {
+                    for span in row.spans.iter_mut() {
+                        span.style = span.style.bg(Color::DarkGray);
+                    }
+                }
+            }
+            total += rows.len();
+            blocks.push(rows);
+            if total >= height {
+                break;
             }
+        }
+
+        let skip = total.checked_sub(height).unwrap_or(0);
+        let rows = blocks.iter().rev().flat_map(|rows| rows.iter()).skip(skip);
+        for (y, row) in rows.enumerate() {
+            draw_row(buf, list_area.left(), top + y as u16, width, row);
+        }
+    }
+}
+
+/// How messages turn into rows: the width they wrap to, and what's needed to render them
+pub struct Layout<'a> {
+    pub width: usize,
+    pub names: &'a Names,
+    pub divider: Option<MessageId>, // last read message, the divider goes after it
+    pub clock: Clock,
+}
+
+impl<'a> Layout<'a> {
+    /// The rows for one message, under a day separator if it's the first of its
+    /// day and the divider if it's the first unread one
+    fn rows(&self, messages: &[Message], index: usize) -> Vec<Line> {
+        let msg = &messages[index];
+        let previous = if index > 0 { Some(&messages[index - 1]) } else { None };
+        let mut rows = vec![];
+        let new_day = previous.map_or(true, |prev| !self.clock.same_day(prev.timestamp, msg.timestamp));
+        if new_day {
+            rows.push(separator(&self.clock.day(msg.timestamp), self.width, Style::default().fg(Color::DarkGray)));
+        }
+        let first_unread = self.divider.map_or(false, |read| {
+            msg.id > read && previous.map_or(true, |prev| prev.id <= read)
+        });
+        if first_unread {
+            rows.push(separator("new messages", self.width, Style::default().fg(Color::Red)));
+        }
+        let grouped = !new_day && !first_unread && previous.map_or(false, |prev| {
+            prev.author.id == msg.author.id
+                && msg.timestamp.signed_duration_since(prev.timestamp) < Duration::minutes(GROUP_MINUTES)
+        });
+        rows.extend(layout(msg, self.width, self.names, self.clock.stamp(msg.timestamp), grouped));
+        rows
+    }
+
+    /// How many rows each of the messages from `start` up to `end` takes up
+    pub fn heights(&self, messages: &[Message], start: usize, end: usize) -> Vec<usize> {
+        (start..end).map(|index| self.rows(messages, index).len()).collect()
+    }
+
+    /// The furthest back `scroll` can go: the oldest message sits at the top of
+    /// the area and the screen is still full. Only lays out one screenful,
+    /// each message once.
+    pub fn oldest_page(&self, messages: &[Message], height: usize) -> usize {
+        let mut total = 0;
+        for index in 0..messages.len() {
+            total += self.rows(messages, index).len();
+            if total >= height {
+                return messages.len() - index - 1;
+            }
+        }
+        0
+    }
+}
+
+/// A full-width line with a label in the middle, like "──── new messages ────"
+fn separator(label: &str, width: usize, line_style: Style) -> Line {
+    let label = format!(" {} ", label);
+    let side = width.checked_sub(wrap::width(&label)).unwrap_or(0);
+    let text = format!("{}{}{}", "─".repeat(side / 2), label, "─".repeat(side - side / 2));
+    Line {
+        spans: vec![Span { text, style: line_style }],
+        code: false,
+    }
+}
+
+/// Wraps a message into rows at most `width` columns wide, with continuation
+/// rows lined up under the message body rather than the author's name. Grouped
+/// messages leave the header blank so they sit under the one before.
+fn layout(msg: &discord::model::Message, width: usize, names: &Names, stamp: Option<String>, grouped: bool) -> Vec<Line> {
+    let stamp = stamp.map_or(String::new(), |stamp| format!("{} ", stamp));
+    let author = String::from(names.display_name(&msg.author));
+    let tag = if mentions::is_bot(&msg.author) { " [BOT]" } else { "" };
+    let header = wrap::width(&stamp) + wrap::width(&author) + wrap::width(tag) + 2;
+    let indent = min(header, width / 3);
+    let mut lines = markdown::parse(&msg.content, Style::default(), &|token| names.resolve(token));
+    // Messages that ping us stand out by their author, otherwise it's in their role's color
+    let author_style = if names.mentions_me(msg) {
+        Style::default().fg(Color::Black).bg(Color::Yellow)
+    } else {
+        names.colors.get(&msg.author.id).map_or(style, |&color| Style::default().fg(color))
+    };
+    let header_spans = if grouped && !names.mentions_me(msg) {
+        vec![Span { text: " ".repeat(header), style: Style::default() }]
+    } else {
+        vec![
+            Span { text: stamp, style: Style::default().fg(Color::DarkGray) },
+            Span { text: author, style: author_style },
+            Span { text: String::from(tag), style: Style::default().fg(Color::LightBlue) },
+            Span { text: String::from(": "), style },
+        ]
+    };
+    lines[0].spans.splice(0..0, header_spans);
+    if msg.edited_timestamp.is_some() {
+        let last = lines.len() - 1;
+        lines[last].spans.push(Span {
+            text: String::from(" (edited)"),
+            style: Style::default().fg(Color::DarkGray),
         });
     }
+    wrap::wrap(lines, width, indent)
+}
+
+fn draw_row(buf: &mut Buffer, left: u16, y: u16, width: usize, row: &Line) {
+    if row.code {
+        // Code keeps its background all the way across
+        let fill = row.spans.last().map_or(markdown::code_block_style(), |span| span.style);
+        buf.set_stringn(left, y, &" ".repeat(width), width, &fill);
+    }
+    // One grapheme per cell, wide ones take the cell after them too
+    let mut x = 0;
+    for span in row.spans.iter() {
+        for grapheme in UnicodeSegmentation::graphemes(&span.text[..], true) {
+            let w = wrap::width(grapheme);
+            if x + w > width {
+                return;
+            }
+            buf.get_mut(left + x as u16, y).set_symbol(grapheme).set_style(span.style);
+            for pad in 1..w {
+                buf.get_mut(left + (x + pad) as u16, y).set_symbol("").set_style(span.style);
+            }
+            x += w;
+        }
+    }
 }
@@ -0,0 +1,110 @@
+use chrono::{DateTime, Datelike, FixedOffset, Local, Utc};
+
+/// What goes next to a message's author
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stamp {
+    Absolute, // 14:05
+    Relative, // 5m, 3h, 2d
+    Hidden,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Zone {
+    Local,
+    Fixed(FixedOffset),
+}
+
+/// How message times and days are shown
+#[derive(Debug, Clone, Copy)]
+pub struct Clock {
+    pub stamp: Stamp,
+    pub twelve_hour: bool,
+    pub zone: Zone,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock {
+            stamp: Stamp::Absolute,
+            twelve_hour: false,
+            zone: Zone::Local,
+        }
+    }
+}
+
+impl Clock {
+    /// The time for a message header, always the same width so grouped messages line up
+    pub fn stamp(&self, time: DateTime<FixedOffset>) -> Option<String> {
+        match self.stamp {
+            Stamp::Absolute if self.twelve_hour => Some(self.localize(time).format("%l:%M %p").to_string()),
+            Stamp::Absolute => Some(self.localize(time).format("%H:%M").to_string()),
+            Stamp::Relative => Some(format!("{:>3}", relative(time))),
+            Stamp::Hidden => None,
+        }
+    }
+
+    /// Like "Tuesday, 14 Oct", with the year too if it isn't this one
+    pub fn day(&self, time: DateTime<FixedOffset>) -> String {
+        let time = self.localize(time);
+        if time.year() == self.now().year() {
+            time.format("%A, %-d %b").to_string()
+        } else {
+            time.format("%A, %-d %b %Y").to_string()
+        }
+    }
+
+    pub fn same_day(&self, a: DateTime<FixedOffset>, b: DateTime<FixedOffset>) -> bool {
+        self.localize(a).naive_local().date() == self.localize(b).naive_local().date()
+    }
+
+    fn now(&self) -> DateTime<FixedOffset> {
+        self.localize(Utc::now().with_timezone(&FixedOffset::east(0)))
+    }
+
+    fn localize(&self, time: DateTime<FixedOffset>) -> DateTime<FixedOffset> {
+        match self.zone {
+            Zone::Local => {
+                let local = time.with_timezone(&Local);
+                time.with_timezone(local.offset())
+            },
+            Zone::Fixed(offset) => time.with_timezone(&offset),
+        }
+    }
+}
+
+/// Roughly how long ago, in the biggest unit that fits
+fn relative(time: DateTime<FixedOffset>) -> String {
+    let minutes = Utc::now().signed_duration_since(time).num_minutes();
+    match minutes {
+        m if m < 1 => String::from("now"),
+        m if m < 60 => format!("{}m", m),
+        m if m < 60 * 24 => format!("{}h", m / 60),
+        m if m < 60 * 24 * 7 => format!("{}d", m / (60 * 24)),
+        m => format!("{}w", m / (60 * 24 * 7)),
+    }
+}
+
+/// `local`, `utc`, or an offset like `+02:00` or `-0530`
+pub fn parse_zone(value: &str) -> Result<Zone, String> {
+    let invalid = || format!("`{}` should be local, utc or an offset like +02:00", value);
+    match value {
+        "local" => return Ok(Zone::Local),
+        "utc" | "UTC" => return Ok(Zone::Fixed(FixedOffset::east(0))),
+        _ => (),
+    }
+    let sign = match value.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => return Err(invalid()),
+    };
+    let digits: String = value[1..].chars().filter(|&c| c != ':').collect();
+    if !digits.chars().all(|c| c.is_ascii_digit()) || (digits.len() != 2 && digits.len() != 4) {
+        return Err(invalid());
+    }
+    let hours: i32 = digits[..2].parse().map_err(|_| invalid())?;
+    let minutes: i32 = if digits.len() == 4 { digits[2..].parse().map_err(|_| invalid())? } else { 0 };
+    if hours > 23 || minutes > 59 {
+        return Err(invalid());
+    }
+    Ok(Zone::Fixed(FixedOffset::east(sign * (hours * 3600 + minutes * 60))))
+}
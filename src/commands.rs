@@ -0,0 +1,283 @@
+use discord::model::UserId;
+
+use complete;
+use complete::Entry;
+use discord_provider::MsgToDiscord;
+use AppState;
+
+/// What a command's argument completes against
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Arg {
+    Nothing,
+    Server,
+    Channel,
+    User,
+    Command,
+}
+
+pub struct Command {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub usage: &'static str,
+    pub help: &'static str,
+    pub arg: Arg,
+    run: fn(&mut AppState, &str) -> Result<(), String>,
+}
+
+pub static COMMANDS: &'static [Command] = &[
+    Command {
+        name: "server",
+        aliases: &["s"],
+        usage: ":server <name>",
+        help: "Switch to the server whose name matches",
+        arg: Arg::Server,
+        run: server,
+    },
+    Command {
+        name: "channel",
+        aliases: &["c"],
+        usage: ":channel <name>",
+        help: "Switch to a channel in this server",
+        arg: Arg::Channel,
+        run: channel,
+    },
+    Command {
+        name: "msg",
+        aliases: &["m"],
+        usage: ":msg @user <text>",
+        help: "Send someone a direct message",
+        arg: Arg::User,
+        run: msg,
+    },
+    Command {
+        name: "nick",
+        aliases: &[],
+        usage: ":nick [name]",
+        help: "Set your nickname in this server, or reset it",
+        arg: Arg::Nothing,
+        run: nick,
+    },
+    Command {
+        name: "topic",
+        aliases: &[],
+        usage: ":topic",
+        help: "Show this channel's topic",
+        arg: Arg::Nothing,
+        run: topic,
+    },
+    Command {
+        name: "dnd",
+        aliases: &[],
+        usage: ":dnd",
+        help: "Turn do-not-disturb on or off",
+        arg: Arg::Nothing,
+        run: dnd,
+    },
+    Command {
+        name: "help",
+        aliases: &["h"],
+        usage: ":help [command]",
+        help: "List commands, or explain one",
+        arg: Arg::Command,
+        run: help,
+    },
+    Command {
+        name: "quit",
+        aliases: &["q"],
+        usage: ":quit",
+        help: "Log out and exit",
+        arg: Arg::Nothing,
+        run: quit,
+    },
+    Command {
+        name: "reload",
+        aliases: &[],
+        usage: ":reload",
+        help: "Fetch servers, channels and messages again",
+        arg: Arg::Nothing,
+        run: reload,
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|command| command.name == name || command.aliases.contains(&name))
+}
+
+/// Runs a line typed after `:`, errors are for the notice area
+pub fn run(state: &mut AppState, line: &str) -> Result<(), String> {
+    let line = line.trim().trim_left_matches(':');
+    let mut parts = line.splitn(2, char::is_whitespace);
+    let name = parts.next().unwrap_or("");
+    let args = parts.next().unwrap_or("").trim();
+    if name.is_empty() {
+        return Ok(());
+    }
+    match find(name) {
+        Some(command) => (command.run)(state, args),
+        None => Err(format!("Unknown command :{}, try :help", name)),
+    }
+}
+
+/// Where the word before the cursor starts, the word itself, and what it could complete to
+pub fn completions(state: &AppState, before_cursor: &str) -> Option<(usize, String, Vec<Entry>)> {
+    let start = before_cursor
+        .char_indices()
+        .filter(|&(_, c)| c.is_whitespace())
+        .last()
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let token = String::from(&before_cursor[start..]);
+    let words: Vec<&str> = before_cursor[..start].split_whitespace().collect();
+    let arg = match words.len() {
+        0 => Arg::Command,
+        // Only the first argument completes, :msg's text is free-form
+        1 => match find(words[0].trim_left_matches(':')) {
+            Some(command) => command.arg,
+            None => return None,
+        },
+        _ => return None,
+    };
+    let entries = match arg {
+        Arg::Nothing => return None,
+        Arg::Command => COMMANDS.iter().map(|command| Entry::literal(command.name)).collect(),
+        Arg::Server => state.servers.iter().map(|server| Entry::literal(&server.server_info.name)).collect(),
+        Arg::Channel => state.servers[state.active_server].channels.iter()
+            .map(|channel| Entry::literal(channel.bare_name()))
+            .collect(),
+        Arg::User => state.completion_entries().into_iter()
+            .filter(|entry| entry.wire.starts_with("<@"))
+            .collect(),
+    };
+    Some((start, token, entries))
+}
+
+/// Exact name first, then the start of a name, then anywhere in it
+fn best_match<'a, I: Iterator<Item = &'a str>>(names: I, wanted: &str) -> Option<usize> {
+    let wanted = wanted.to_lowercase();
+    let names: Vec<String> = names.map(|name| name.to_lowercase()).collect();
+    names.iter().position(|name| *name == wanted)
+        .or_else(|| names.iter().position(|name| name.starts_with(&wanted)))
+        .or_else(|| names.iter().position(|name| name.contains(&wanted)))
+}
+
+fn server(state: &mut AppState, args: &str) -> Result<(), String> {
+    if args.is_empty() {
+        return Err(String::from("Usage: :server <name>"));
+    }
+    let found = best_match(state.servers.iter().map(|server| &server.server_info.name[..]), args);
+    match found {
+        Some(index) => {
+            state.active_server = index;
+            state.open_channel();
+            Ok(())
+        },
+        None => Err(format!("No server matches {}", args)),
+    }
+}
+
+fn channel(state: &mut AppState, args: &str) -> Result<(), String> {
+    let wanted = args.trim_left_matches('#');
+    if wanted.is_empty() {
+        return Err(String::from("Usage: :channel <name>"));
+    }
+    let found = {
+        let server = &state.servers[state.active_server];
+        best_match(server.channels.iter().map(|channel| channel.bare_name()), wanted)
+    };
+    match found {
+        Some(index) => {
+            state.servers[state.active_server].active_channel = index;
+            state.open_channel();
+            Ok(())
+        },
+        None => Err(format!("No channel here matches {}", wanted)),
+    }
+}
+
+fn msg(state: &mut AppState, args: &str) -> Result<(), String> {
+    let entries = state.completion_entries();
+    // Names can have spaces in them, so take the longest one that fits
+    let recipient = entries.iter()
+        .filter(|entry| entry.wire.starts_with("<@") && args.starts_with(&entry.key[..]))
+        .max_by_key(|entry| entry.key.len());
+    let (recipient, text) = match recipient {
+        Some(entry) => (entry, args[entry.key.len()..].trim()),
+        None if args.starts_with('@') => return Err(format!("Don't know who {} is", args.split_whitespace().next().unwrap_or(args))),
+        None => return Err(String::from("Usage: :msg @user <text>")),
+    };
+    if text.is_empty() {
+        return Err(format!("Nothing to send to {}", recipient.key));
+    }
+    let id = match recipient.wire.trim_matches(|c| c == '<' || c == '>' || c == '@').parse() {
+        Ok(id) => UserId(id),
+        Err(_) => return Err(format!("Can't message {}", recipient.key)),
+    };
+    // Nothing after the recipient completes, so only :emoji: get swapped
+    let text = complete::encode(text, &[], &entries);
+    state.to_provider.send(MsgToDiscord::SendDirectMessage(id, text));
+    Ok(())
+}
+
+fn nick(state: &mut AppState, args: &str) -> Result<(), String> {
+    let server = state.servers[state.active_server].server_info.id;
+    if state.servers[state.active_server].is_private() {
+        return Err(String::from("Nicknames are per server, switch to one first"));
+    }
+    state.to_provider.send(MsgToDiscord::SetNickname(server, String::from(args)));
+    Ok(())
+}
+
+fn topic(state: &mut AppState, _: &str) -> Result<(), String> {
+    let topic = match state.active_channel_ref() {
+        Some(channel) => match channel.topic {
+            Some(ref topic) if !topic.is_empty() => format!("#{}: {}", channel.bare_name(), topic),
+            _ => format!("#{} has no topic", channel.bare_name()),
+        },
+        None => return Err(String::from("No channel open")),
+    };
+    state.notify(topic);
+    Ok(())
+}
+
+fn dnd(state: &mut AppState, _: &str) -> Result<(), String> {
+    state.dnd = !state.dnd;
+    let status = if state.dnd { "Do not disturb: notifications are off" } else { "Notifications are back on" };
+    state.notify(String::from(status));
+    Ok(())
+}
+
+fn help(state: &mut AppState, args: &str) -> Result<(), String> {
+    if args.is_empty() {
+        let names: Vec<&str> = COMMANDS.iter().map(|command| command.name).collect();
+        state.notify(format!("Commands: {}. :help <command> for more", names.join(", ")));
+        return Ok(());
+    }
+    match find(args.trim_left_matches(':')) {
+        Some(command) => {
+            state.notify(format!("{} - {}", command.usage, command.help));
+            Ok(())
+        },
+        None => Err(format!("No command called {}", args)),
+    }
+}
+
+fn quit(state: &mut AppState, _: &str) -> Result<(), String> {
+    state.quit();
+    Ok(())
+}
+
+fn reload(state: &mut AppState, _: &str) -> Result<(), String> {
+    for channel in state.servers.iter_mut().flat_map(|server| server.channels.iter_mut()) {
+        channel.messages.clear();
+        channel.history_requested = false;
+        channel.loading_older = false;
+        channel.reached_start = false;
+        channel.gap_after = None;
+        channel.backfilling = false;
+    }
+    state.scroll_pos = 0;
+    state.selected = None;
+    state.get_servers();
+    state.open_channel();
+    state.notify(String::from("Reloading servers and messages"));
+    Ok(())
+}
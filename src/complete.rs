@@ -0,0 +1,205 @@
+use discord::model::{ChannelId, EmojiId, UserId};
+
+use editor::Completed;
+use emoji;
+
+/// Something that `@`, `#` or `:` can complete to
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub key: String,    // what's typed: @name, #channel, :emoji:
+    pub insert: String, // what completing it puts in the input
+    pub wire: String,   // what it turns into when the message is sent
+    pub label: String,  // how it's shown in the candidate list
+}
+
+impl Entry {
+    pub fn user(name: &str, id: UserId) -> Self {
+        let key = format!("@{}", name);
+        Entry {
+            insert: format!("{} ", key),
+            wire: format!("<@{}>", id.0),
+            label: key.clone(),
+            key,
+        }
+    }
+    pub fn channel(name: &str, id: ChannelId) -> Self {
+        let key = format!("#{}", name);
+        Entry {
+            insert: format!("{} ", key),
+            wire: format!("<#{}>", id.0),
+            label: key.clone(),
+            key,
+        }
+    }
+    pub fn custom_emoji(name: &str, id: EmojiId) -> Self {
+        let key = format!(":{}:", name);
+        Entry {
+            insert: format!("{} ", key),
+            wire: format!("<:{}:{}>", name, id.0),
+            label: format!("{} (custom)", key),
+            key,
+        }
+    }
+    /// Unicode emoji go straight into the input, there's nothing to translate later
+    pub fn emoji(shortcode: &str, emoji: &str) -> Self {
+        let key = format!(":{}:", shortcode);
+        Entry {
+            insert: format!("{} ", emoji),
+            wire: String::from(emoji),
+            label: format!("{} {}", emoji, key),
+            key,
+        }
+    }
+    /// @everyone and @here are already what Discord wants
+    pub fn literal(key: &str) -> Self {
+        Entry {
+            key: String::from(key),
+            insert: format!("{} ", key),
+            wire: String::from(key),
+            label: String::from(key),
+        }
+    }
+    pub fn labelled(mut self, label: String) -> Self {
+        self.label = label;
+        self
+    }
+    /// How much of what's inserted stands for `wire`, if it needs swapping when sent
+    pub fn encodes(&self) -> Option<usize> {
+        if self.key != self.wire && self.insert.starts_with(&self.key[..]) {
+            Some(self.key.len())
+        } else {
+            None
+        }
+    }
+}
+
+pub fn emoji_entries() -> Vec<Entry> {
+    emoji::SHORTCODES.iter().map(|&(shortcode, emoji)| Entry::emoji(shortcode, emoji)).collect()
+}
+
+/// Tab completion in progress: the token it started from and the candidates being cycled through
+pub struct Completion {
+    pub start: usize, // byte offset of the token in the input
+    pub original: String,
+    pub candidates: Vec<Entry>,
+    pub index: usize,
+}
+
+impl Completion {
+    pub fn current(&self) -> &Entry {
+        &self.candidates[self.index]
+    }
+    pub fn step(&mut self, step: isize) {
+        let n = self.candidates.len() as isize;
+        self.index = ((self.index as isize + step) % n + n) as usize % self.candidates.len();
+    }
+    pub fn labels(&self) -> Vec<String> {
+        self.candidates.iter().map(|entry| entry.label.clone()).collect()
+    }
+}
+
+/// The word the cursor is at the end of, if it's something we can complete
+pub fn token(before_cursor: &str) -> Option<(usize, &str)> {
+    let start = before_cursor
+        .char_indices()
+        .filter(|&(_, c)| c.is_whitespace())
+        .last()
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let token = &before_cursor[start..];
+    match token.chars().next() {
+        Some('@') | Some('#') | Some(':') => Some((start, token)),
+        _ => None,
+    }
+}
+
+/// Entries whose key starts with `token`, ignoring case, first one of each key wins
+pub fn candidates(entries: &[Entry], token: &str) -> Vec<Entry> {
+    let token = token.to_lowercase();
+    let mut found: Vec<Entry> = vec![];
+    for entry in entries.iter() {
+        if entry.key.to_lowercase().starts_with(&token) && !found.iter().any(|seen| seen.key == entry.key) {
+            found.push(entry.clone());
+        }
+    }
+    found
+}
+
+/// Swaps what completions put in for what Discord expects, and `:emoji:` typed
+/// out by hand too, outside of code. A plain `@name` or `#channel` is left as it
+/// is, the text could just as well be talking about one.
+pub fn encode(text: &str, completed: &[Completed], entries: &[Entry]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut code = false;
+    let mut i = 0;
+    while i < text.len() {
+        if let Some(span) = completed.iter().find(|span| span.start == i) {
+            out.push_str(&span.wire);
+            i = span.end;
+            continue;
+        }
+        let rest = &text[i..];
+        let c = rest.chars().next().unwrap();
+        if c == '`' {
+            code = !code;
+        } else if !code && c == ':' {
+            let found = entries.iter()
+                .filter(|entry| entry.key.starts_with(':') && entry.key != entry.wire && rest.starts_with(&entry.key[..]))
+                .max_by_key(|entry| entry.key.len());
+            if let Some(entry) = found {
+                out.push_str(&entry.wire);
+                i += entry.key.len();
+                continue;
+            }
+        }
+        out.push(c);
+        i += c.len_utf8();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(start: usize, end: usize, wire: &str) -> Completed {
+        Completed { start, end, wire: String::from(wire) }
+    }
+
+    #[test]
+    fn only_completed_names_are_encoded() {
+        let entries = vec![Entry::user("anna", UserId(1))];
+        let text = "hi @anna and @anna";
+        assert_eq!(encode(text, &[span(3, 8, "<@1>")], &entries), "hi <@1> and @anna");
+        assert_eq!(encode(text, &[], &entries), text);
+    }
+
+    #[test]
+    fn typed_emoji_are_left_alone_in_code() {
+        let entries = vec![Entry::emoji("smile", "😄")];
+        assert_eq!(encode("`:smile:` :smile:", &[], &entries), "`:smile:` 😄");
+        assert_eq!(encode("```\n:smile:\n```", &[], &entries), "```\n:smile:\n```");
+    }
+
+    #[test]
+    fn completions_in_code_are_still_encoded() {
+        let entries = vec![Entry::user("anna", UserId(1))];
+        assert_eq!(encode("`@anna`", &[span(1, 6, "<@1>")], &entries), "`<@1>`");
+    }
+
+    #[test]
+    fn typed_custom_emoji_are_encoded() {
+        let entries = vec![
+            Entry::custom_emoji("party", EmojiId(5)),
+            Entry::custom_emoji("party_parrot", EmojiId(6)),
+        ];
+        assert_eq!(encode(":party_parrot: :party:", &[], &entries), "<:party_parrot:6> <:party:5>");
+    }
+
+    #[test]
+    fn only_names_that_change_are_encoded() {
+        assert_eq!(Entry::user("anna", UserId(1)).encodes(), Some(5));
+        assert_eq!(Entry::channel("general", ChannelId(2)).encodes(), Some(8));
+        assert_eq!(Entry::emoji("smile", "😄").encodes(), None);
+        assert_eq!(Entry::literal("@here").encodes(), None);
+    }
+}
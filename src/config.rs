@@ -0,0 +1,127 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+
+use clock;
+use clock::{Clock, Stamp};
+use notify::Level;
+
+/// Settings from the `./config` file, one `key = value` per line. Anything
+/// missing keeps its default.
+pub struct Config {
+    /// How many rows the message input grows to before it scrolls instead
+    pub input_max_height: usize,
+    /// Which new messages notify, unless a rule below says otherwise
+    pub notify: Level,
+    /// `notify.<server> = ...` and `notify.<server>#<channel> = ...`, by lowercase name
+    pub notify_rules: HashMap<String, Level>,
+    /// Shell command run for each notification
+    pub notify_command: Option<String>,
+    /// Start out in do-not-disturb
+    pub dnd: bool,
+    /// Message times: `timestamps`, `clock` and `timezone`
+    pub clock: Clock,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            input_max_height: 8,
+            notify: Level::Mentions,
+            notify_rules: HashMap::new(),
+            notify_command: None,
+            dnd: false,
+            clock: Clock::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config file if there is one, along with complaints about any
+    /// lines that couldn't be used
+    pub fn load() -> (Self, Vec<String>) {
+        let mut config = Config::default();
+        let mut problems = vec![];
+        let mut data = String::new();
+        match File::open("./config") {
+            Ok(mut f) => {
+                if let Err(error) = f.read_to_string(&mut data) {
+                    problems.push(format!("Couldn't read config: {}", error));
+                }
+            },
+            Err(_) => return (config, problems),
+        }
+
+        for (n, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = match parts.next() {
+                Some(value) => value.trim(),
+                None => {
+                    problems.push(format!("config line {}: expected `key = value`", n + 1));
+                    continue;
+                },
+            };
+            if let Err(problem) = config.set(key, value) {
+                problems.push(format!("config line {}: {}", n + 1, problem));
+            }
+        }
+        (config, problems)
+    }
+
+    /// The most specific rule for a channel: its own, then its server's, then the default
+    pub fn notify_level(&self, server: &str, channel: &str) -> Level {
+        let server = server.to_lowercase();
+        self.notify_rules.get(&format!("{}#{}", server, channel.to_lowercase()))
+            .or_else(|| self.notify_rules.get(&server))
+            .cloned()
+            .unwrap_or(self.notify)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        if key.starts_with("notify.") {
+            let place = key["notify.".len()..].trim().to_lowercase();
+            self.notify_rules.insert(place, Level::parse(value)?);
+            return Ok(());
+        }
+        match key {
+            "input_max_height" => self.input_max_height = number(value)?,
+            "notify" => self.notify = Level::parse(value)?,
+            "notify_command" => self.notify_command = if value.is_empty() { None } else { Some(String::from(value)) },
+            "dnd" => self.dnd = boolean(value)?,
+            "timestamps" => self.clock.stamp = match value {
+                "absolute" => Stamp::Absolute,
+                "relative" => Stamp::Relative,
+                "none" => Stamp::Hidden,
+                _ => return Err(format!("`{}` should be absolute, relative or none", value)),
+            },
+            "clock" => self.clock.twelve_hour = match value {
+                "12h" => true,
+                "24h" => false,
+                _ => return Err(format!("`{}` should be 12h or 24h", value)),
+            },
+            "timezone" => self.clock.zone = clock::parse_zone(value)?,
+            _ => return Err(format!("unknown setting `{}`", key)),
+        }
+        Ok(())
+    }
+}
+
+fn number(value: &str) -> Result<usize, String> {
+    match value.parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("`{}` should be a positive number", value)),
+    }
+}
+
+fn boolean(value: &str) -> Result<bool, String> {
+    match value {
+        "true" | "yes" | "on" => Ok(true),
+        "false" | "no" | "off" => Ok(false),
+        _ => Err(format!("`{}` should be true or false", value)),
+    }
+}
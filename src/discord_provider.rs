@@ -1,10 +1,14 @@
+use discord;
 use discord::{Discord, Connection, GetMessages};
 use discord::model::*;
 use chan::{Sender, Receiver};
 use chan;
+use chrono::{DateTime, FixedOffset};
 use thread;
 use std::cmp::min;
 use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 //#[derive(Debug)]
 pub struct DiscordProvider {
@@ -18,7 +22,37 @@ pub struct DiscordProvider {
 pub enum MsgFromDiscord {
     Servers(Vec<ServerInfo>),
     Channels(ServerId, Vec<PublicChannel>),
+    PrivateChannels(Vec<Channel>), // DMs and group DMs
+    CurrentUser(CurrentUser),
+    LiveServer(LiveServer), // members, roles and emojis, once a server is available
+    ReadState(Vec<ReadState>), // how far we'd read everywhere, from READY
+    Read(ChannelId, Option<MessageId>), // read up to here on some other client
+    MemberUpdate {
+        server_id: ServerId,
+        user: User,
+        roles: Vec<RoleId>,
+        nick: Option<String>,
+    },
+    Member(ServerId, Member), // joined, or fetched because they weren't in the list
+    MemberRemove(ServerId, UserId),
+    RoleUpdate(ServerId, Role), // created or changed
+    RoleDelete(ServerId, RoleId),
     ChatMsg(Message),
+    History(ChannelId, Vec<Message>, bool), // true once there is nothing older to fetch
+    MessageUpdate {
+        channel_id: ChannelId,
+        id: MessageId,
+        content: Option<String>,
+        edited_timestamp: Option<DateTime<FixedOffset>>,
+    },
+    MessageDelete(ChannelId, MessageId),
+    MessageDeleteBulk(ChannelId, Vec<MessageId>),
+    ConnectionState(ConnectionState),
+    GatewayError(ProviderError), // something went wrong on the websocket that isn't a lost connection
+    Error {
+        request: MsgToDiscord, // handed back so the UI can recover, e.g. an unsent draft
+        error: ProviderError,
+    },
 	Exit, // FIN-ACK basically
     EchoResponse(String)
 }
@@ -28,20 +62,174 @@ pub enum MsgToDiscord {
     GetChannels(ServerId),
     GetMessages(ChannelId, GetMessages, usize),
     SendMessage(ChannelId, String),
+    EditMessage(ChannelId, MessageId, String),
+    DeleteMessage(ChannelId, MessageId),
+    AddReaction(ChannelId, MessageId, String), // unicode emoji, or name:id for a custom one
+    SendDirectMessage(UserId, String), // opens the DM first if there isn't one yet
+    SetNickname(ServerId, String), // our own, empty to reset it
+    GetMember(ServerId, UserId),
+    Ack(ChannelId, MessageId), // mark read up to this message
     Logout, // FIN
     Echo(String), // Testing echo back what we got
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionState {
+    Connected,
+    Reconnecting(u32), // attempt number
+    Offline, // still retrying, but it's been a while
+}
+
+impl fmt::Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ConnectionState::Connected => write!(f, "connected"),
+            &ConnectionState::Reconnecting(attempt) => write!(f, "reconnecting (attempt {})", attempt),
+            &ConnectionState::Offline => write!(f, "offline"),
+        }
+    }
+}
+
+/// Failed attempts before we stop calling it reconnecting and call it offline
+const OFFLINE_AFTER: u32 = 5;
+const MAX_BACKOFF_SECS: u64 = 60;
+
+/// Exponential backoff for gateway reconnects
+struct Backoff {
+    attempt: u32,
+    next_try: Instant,
+}
+
+impl Backoff {
+    fn new() -> Self {
+        Backoff {
+            attempt: 0,
+            next_try: Instant::now(),
+        }
+    }
+    /// Schedule the next attempt: 1s, 2s, 4s, ... capped, plus up to 50% jitter
+    /// so a server restart doesn't get every client back at the same moment
+    fn failed(&mut self) {
+        self.attempt += 1;
+        let base = min(1u64 << min(self.attempt - 1, 6), MAX_BACKOFF_SECS) * 1000;
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos() as u64)
+            .unwrap_or(0);
+        let jitter = nanos % (base / 2 + 1);
+        self.next_try = Instant::now() + Duration::from_millis(base + jitter);
+    }
+    fn state(&self) -> ConnectionState {
+        if self.attempt >= OFFLINE_AFTER {
+            ConnectionState::Offline
+        } else {
+            ConnectionState::Reconnecting(self.attempt + 1)
+        }
+    }
+}
+
 impl fmt::Debug for MsgToDiscord {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use MsgToDiscord::*;
         match self {
+            &GetServers => write!(f, "GetServers"),
+            &GetChannels(x) => write!(f, "GetChannels({:?})", x),
             &GetMessages(x,_,z) => write!(f, "Get {} messages from {:?}", z, x),
-            x => write!(f,"{:?}", x)
+            &SendMessage(x, ref y) => write!(f, "SendMessage({:?}, {:?})", x, y),
+            &EditMessage(x, y, ref z) => write!(f, "EditMessage({:?}, {:?}, {:?})", x, y, z),
+            &DeleteMessage(x, y) => write!(f, "DeleteMessage({:?}, {:?})", x, y),
+            &AddReaction(x, y, ref z) => write!(f, "AddReaction({:?}, {:?}, {:?})", x, y, z),
+            &SendDirectMessage(x, ref y) => write!(f, "SendDirectMessage({:?}, {:?})", x, y),
+            &SetNickname(x, ref y) => write!(f, "SetNickname({:?}, {:?})", x, y),
+            &GetMember(x, y) => write!(f, "GetMember({:?}, {:?})", x, y),
+            &Ack(x, y) => write!(f, "Ack({:?}, {:?})", x, y),
+            &Logout => write!(f, "Logout"),
+            &Echo(ref x) => write!(f, "Echo({:?})", x),
+        }
+    }
+}
+
+/// Why a request failed, sorted into the cases worth explaining to the user
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProviderError {
+    MissingPermissions,
+    RateLimited(u64), // milliseconds until we may try again
+    UnknownChannel, // deleted, or we were removed from it
+    Network(String),
+    Other(String),
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ProviderError::MissingPermissions => write!(f, "missing permissions"),
+            &ProviderError::RateLimited(ms) => write!(f, "rate limited, try again in {:.1}s", ms as f64 / 1000.0),
+            &ProviderError::UnknownChannel => write!(f, "the channel no longer exists"),
+            &ProviderError::Network(ref reason) => write!(f, "network error: {}", reason),
+            &ProviderError::Other(ref reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl From<discord::Error> for ProviderError {
+    fn from(error: discord::Error) -> Self {
+        let description = error.to_string();
+        match error {
+            discord::Error::RateLimited(ms) => ProviderError::RateLimited(ms),
+            discord::Error::Status(status, body) => {
+                // Discord's JSON error codes are more specific than the HTTP status
+                let field = |name: &str| body.as_ref()
+                    .and_then(|body| body.get(name))
+                    .and_then(|value| value.as_u64());
+                match (status.to_u16(), field("code")) {
+                    (_, Some(50001)) | (_, Some(50013)) | (403, _) => ProviderError::MissingPermissions,
+                    (_, Some(10003)) => ProviderError::UnknownChannel,
+                    (429, _) => ProviderError::RateLimited(field("retry_after").unwrap_or(0)),
+                    _ => ProviderError::Other(description),
+                }
+            },
+            discord::Error::Hyper(_) |
+            discord::Error::WebSocket(_) |
+            discord::Error::Io(_) |
+            discord::Error::Closed(..) => ProviderError::Network(description),
+            _ => ProviderError::Other(description),
         }
     }
 }
 
+/// `name:id` or `<:name:id>` is a custom emoji, anything else is sent as unicode
+fn reaction_emoji(emoji: &str) -> ReactionEmoji {
+    let trimmed = emoji.trim_matches(|c| c == '<' || c == '>').trim_left_matches("a:").trim_left_matches(':');
+    let mut parts = trimmed.rsplitn(2, ':');
+    if let (Some(id), Some(name)) = (parts.next(), parts.next()) {
+        if let Ok(id) = id.parse() {
+            return ReactionEmoji::Custom {
+                name: String::from(name),
+                id: EmojiId(id),
+            };
+        }
+    }
+    ReactionEmoji::Unicode(String::from(emoji))
+}
+
+/// `GetMessages` isn't Clone, but failed requests are handed back to the UI
+fn copy_what(what: &GetMessages) -> GetMessages {
+    match what {
+        &GetMessages::MostRecent => GetMessages::MostRecent,
+        &GetMessages::Before(id) => GetMessages::Before(id),
+        &GetMessages::After(id) => GetMessages::After(id),
+        &GetMessages::Around(id) => GetMessages::Around(id),
+    }
+}
+
+
+/// A chat service the UI can talk to. The UI only ever sends `MsgToDiscord`
+/// requests and reacts to `MsgFromDiscord` replies, so anything speaking that
+/// protocol over the two channels can stand in for Discord.
+pub trait ChatBackend: Send {
+    /// Serve requests until the UI sends `MsgToDiscord::Logout`
+    fn start_provider(self: Box<Self>);
+}
 
 impl DiscordProvider {
     pub fn init(
@@ -54,142 +242,407 @@ impl DiscordProvider {
             rx: channel.1,
         }
     }
+}
 
-    pub fn start_provider(self) {
-        let discord_client = self.discord
-            .expect("Login to discord first!");
-        let mut connection = discord_client
-            .connect()
-            .expect("Failed to initialize websocket connection")
-            .0;
+impl ChatBackend for DiscordProvider {
+    fn start_provider(self: Box<Self>) {
+        let provider = *self;
+        let discord_client = Arc::new(provider.discord
+            .expect("Login to discord first!"));
+        // Without a connection we still serve requests, the websocket thread
+        // keeps trying and sends everything from READY once it gets through
+        let (connection, ready) = match discord_client.connect() {
+            Ok((connection, ready)) => (Some(connection), Some(ready)),
+            Err(error) => {
+                provider.tx.send(MsgFromDiscord::GatewayError(error.into()));
+                (None, None)
+            },
+        };
 
-        let (sender, reciever) = chan::async();
 		let (sdone, rdone) = chan::async();
-        thread::spawn(move || monitor_websocket(connection, sender,rdone));
-        
-        handle_messages(discord_client, self.tx, self.rx, reciever, sdone)
+        {
+            let discord = Arc::clone(&discord_client);
+            let ui_sender = provider.tx.clone();
+            thread::spawn(move || monitor_websocket(&discord, connection, ui_sender, rdone));
+        }
+        let (members, member_requests) = chan::async();
+        {
+            let discord = Arc::clone(&discord_client);
+            let ui_sender = provider.tx.clone();
+            thread::spawn(move || fetch_members(&discord, member_requests, ui_sender));
+        }
+
+        let private_channels = match ready {
+            Some(ready) => {
+                provider.tx.send(MsgFromDiscord::ConnectionState(ConnectionState::Connected));
+                provider.tx.send(MsgFromDiscord::CurrentUser(ready.user));
+                send_live_servers(ready.servers, &provider.tx);
+                provider.tx.send(MsgFromDiscord::ReadState(ready.read_state.unwrap_or_default()));
+                ready.private_channels
+            },
+            None => vec![],
+        };
+        handle_messages(&discord_client, private_channels, provider.tx, provider.rx, members, sdone)
     }
 }
 
-fn monitor_websocket(mut connection: Connection, discord_sender: Sender<Event>, close:Receiver<()>) {
+/// Owns the websocket: forwards its events to the UI and gets the connection
+/// back when it drops, so requests never wait on a reconnect
+fn monitor_websocket(
+    discord: &Discord,
+    mut connection: Option<Connection>,
+    ui_sender: Sender<MsgFromDiscord>,
+    close: Receiver<()>) {
+    let mut reconnect = match connection {
+        Some(_) => None,
+        None => {
+            let backoff = Backoff::new();
+            ui_sender.send(MsgFromDiscord::ConnectionState(backoff.state()));
+            Some(backoff)
+        },
+    };
     loop {
 		chan_select! {
 			default => (),
-			close.recv() => {println!("Websocket closing."); break;},
-            
+			close.recv() => break,
 		}
-        let event = match connection.recv_event() {
-            Ok(event) => discord_sender.send(event),
-            Err(error) => {
-                // Don't spam if something goes wrong
-                thread::sleep_ms(1000);
+        if let Some(ref backoff) = reconnect {
+            if Instant::now() < backoff.next_try {
+                thread::sleep(Duration::from_millis(100));
                 continue;
             }
+        }
+        let event = match connection.take() {
+            Some(mut current) => {
+                let event = current.recv_event();
+                connection = Some(current);
+                event
+            },
+            // Resuming didn't work out, start a new session
+            None => match discord.connect() {
+                Ok((new_connection, ready)) => {
+                    connection = Some(new_connection);
+                    Ok(Event::Ready(ready))
+                },
+                Err(error) => {
+                    if let Some(ref mut backoff) = reconnect {
+                        backoff.failed();
+                        ui_sender.send(MsgFromDiscord::ConnectionState(backoff.state()));
+                    }
+                    // Lost connections only show in the status bar, anything else is worth a notice
+                    if !is_disconnect(&error) {
+                        ui_sender.send(MsgFromDiscord::GatewayError(error.into()));
+                    }
+                    continue;
+                },
+            },
         };
+        match event {
+            Ok(Event::Ready(ready)) => {
+                // A new session, either ours or one recv_event identified for
+                // when the old one couldn't be resumed
+                ui_sender.send(MsgFromDiscord::CurrentUser(ready.user));
+                ui_sender.send(MsgFromDiscord::PrivateChannels(ready.private_channels));
+                send_live_servers(ready.servers, &ui_sender);
+                ui_sender.send(MsgFromDiscord::ReadState(ready.read_state.unwrap_or_default()));
+                if reconnect.take().is_some() {
+                    // The UI backfills whatever it missed when it sees this
+                    ui_sender.send(MsgFromDiscord::ConnectionState(ConnectionState::Connected));
+                }
+            },
+            Ok(event) => {
+                if reconnect.take().is_some() {
+                    ui_sender.send(MsgFromDiscord::ConnectionState(ConnectionState::Connected));
+                }
+                handle_event(event, &ui_sender);
+            },
+            Err(ref error) if is_disconnect(error) => {
+                // recv_event already tries to resume with the session id and
+                // sequence, then to identify again. Give that one more go on the
+                // same connection before throwing the session away.
+                let backoff = match reconnect.take() {
+                    Some(mut backoff) => {
+                        backoff.failed();
+                        connection = None;
+                        backoff
+                    },
+                    None => Backoff::new(),
+                };
+                ui_sender.send(MsgFromDiscord::ConnectionState(backoff.state()));
+                reconnect = Some(backoff);
+            },
+            Err(error) => {
+                // Likely an event we couldn't decode, the connection itself is fine.
+                // Don't spam if something goes wrong
+                ui_sender.send(MsgFromDiscord::GatewayError(error.into()));
+                thread::sleep(Duration::from_millis(1000));
+            },
+        }
+    }
+}
 
-    };
+/// Loading a big server's history can ask for lots of members one at a time,
+/// they're looked up here so sending a message doesn't wait behind them
+fn fetch_members(discord: &Discord, requests: Receiver<(ServerId, UserId)>, ui_sender: Sender<MsgFromDiscord>) {
+    while let Some((server, user)) = requests.recv() {
+        match discord.get_member(server, user) {
+            Ok(member) => ui_sender.send(MsgFromDiscord::Member(server, member)),
+            Err(error) => ui_sender.send(MsgFromDiscord::Error {
+                request: MsgToDiscord::GetMember(server, user),
+                error: error.into(),
+            }),
+        }
+    }
+}
+
+/// Errors that mean the websocket is gone rather than one event going wrong
+fn is_disconnect(error: &discord::Error) -> bool {
+    match error {
+        &discord::Error::Closed(..) |
+        &discord::Error::WebSocket(..) |
+        &discord::Error::Io(..) => true,
+        _ => false,
+    }
 }
 
 // Handle messages to and from the main module
 fn handle_messages(
-    discord: Discord,
+    discord: &Discord,
+    private_channels: Vec<Channel>,
     ui_sender: Sender<MsgFromDiscord>,
     ui_reciever: Receiver<MsgToDiscord>,
-    discord_reciever: Receiver<Event>,
+    members: Sender<(ServerId, UserId)>,
 	close:Sender<()>) {
     loop {
-        chan_select! {
-            default => {},
-            ui_reciever.recv() -> val => {
-                let message = val.unwrap();
-                //println!("{:?}", message);
-                match message {
-                    MsgToDiscord::GetServers => {
-                        let s = discord.get_servers();
-                        if let Ok(servers) = s {
-                            ui_sender.send(MsgFromDiscord::Servers(servers));
-                        }
-                    },
-                    MsgToDiscord::GetChannels(server_id) => {
-                        let c = discord.get_server_channels(server_id);
-                        if let Ok(channels) = c {
-                            ui_sender.send(MsgFromDiscord::Channels(server_id, channels));
-                        }
-                    }
-                    MsgToDiscord::SendMessage(channel, content) => {
-                        discord.send_message(channel, &content, "", false);
-                    },
-                    MsgToDiscord::Logout => {
-						close.send(());
-						ui_sender.send(MsgFromDiscord::Exit);
-						return;	
-                    }
-                    MsgToDiscord::Echo(message) => {
-                        ui_sender.send(MsgFromDiscord::EchoResponse(message));
-                    },
-                    MsgToDiscord::GetMessages(id, gm, count) => (),
-                    x => panic!("Unrecognized message {:?}",x)
+        let message = match ui_reciever.recv() {
+            Some(message) => message,
+            None => return,
+        };
+        //println!("{:?}", message);
+        let fail = |request, error: discord::Error| {
+            ui_sender.send(MsgFromDiscord::Error { request, error: error.into() });
+        };
+        match message {
+            MsgToDiscord::GetServers => {
+                match discord.get_servers() {
+                    Ok(servers) => ui_sender.send(MsgFromDiscord::Servers(servers)),
+                    Err(error) => fail(MsgToDiscord::GetServers, error),
                 }
+                // DMs come from the READY payload rather than a REST call
+                ui_sender.send(MsgFromDiscord::PrivateChannels(private_channels.clone()));
             },
-            discord_reciever.recv() -> val => {
-                let event = val.unwrap();
-                match event {
-                    Event::MessageCreate(msg) => {
-                        ui_sender.send(MsgFromDiscord::ChatMsg(msg));
+            MsgToDiscord::GetChannels(server_id) => {
+                match discord.get_server_channels(server_id) {
+                    Ok(channels) => ui_sender.send(MsgFromDiscord::Channels(server_id, channels)),
+                    Err(error) => fail(MsgToDiscord::GetChannels(server_id), error),
+                }
+            }
+            MsgToDiscord::SendMessage(channel, content) => {
+                if let Err(error) = discord.send_message(channel, &content, "", false) {
+                    fail(MsgToDiscord::SendMessage(channel, content), error);
+                }
+            },
+            MsgToDiscord::EditMessage(channel, id, content) => {
+                // The gateway echoes this too, but don't make the UI wait for it
+                match discord.edit_message(channel, id, &content) {
+                    Ok(msg) => ui_sender.send(MsgFromDiscord::MessageUpdate {
+                        channel_id: msg.channel_id,
+                        id: msg.id,
+                        content: Some(msg.content),
+                        edited_timestamp: msg.edited_timestamp,
+                    }),
+                    Err(error) => fail(MsgToDiscord::EditMessage(channel, id, content), error),
+                }
+            },
+            MsgToDiscord::DeleteMessage(channel, id) => {
+                match discord.delete_message(channel, id) {
+                    Ok(()) => ui_sender.send(MsgFromDiscord::MessageDelete(channel, id)),
+                    Err(error) => fail(MsgToDiscord::DeleteMessage(channel, id), error),
+                }
+            },
+            MsgToDiscord::AddReaction(channel, id, emoji) => {
+                if let Err(error) = discord.add_reaction(channel, id, reaction_emoji(&emoji)) {
+                    fail(MsgToDiscord::AddReaction(channel, id, emoji), error);
+                }
+            },
+            MsgToDiscord::SendDirectMessage(user, content) => {
+                let sent = discord.create_private_channel(user).and_then(|dm| {
+                    discord.send_message(dm.id, &content, "", false)?;
+                    Ok(dm)
+                });
+                match sent {
+                    Ok(dm) => ui_sender.send(MsgFromDiscord::PrivateChannels(vec![Channel::Private(dm)])),
+                    Err(error) => fail(MsgToDiscord::SendDirectMessage(user, content), error),
+                }
+            },
+            MsgToDiscord::SetNickname(server, nick) => {
+                // The gateway tells us about the new nickname with a member update
+                if let Err(error) = discord.edit_nickname(server, &nick) {
+                    fail(MsgToDiscord::SetNickname(server, nick), error);
+                }
+            },
+            MsgToDiscord::GetMember(server, user) => members.send((server, user)),
+            MsgToDiscord::Ack(channel, message) => {
+                if let Err(error) = discord.ack_message(channel, message) {
+                    fail(MsgToDiscord::Ack(channel, message), error);
+                }
+            },
+            MsgToDiscord::Logout => {
+                close.send(());
+                ui_sender.send(MsgFromDiscord::Exit);
+                return;
+            }
+            MsgToDiscord::Echo(message) => {
+                ui_sender.send(MsgFromDiscord::EchoResponse(message));
+            },
+            MsgToDiscord::GetMessages(channel, what, count) => {
+                // A short Around page only means the message is near the newest end,
+                // it says nothing about how far back the channel goes
+                let backwards = match what {
+                    GetMessages::MostRecent | GetMessages::Before(_) => true,
+                    GetMessages::Around(_) | GetMessages::After(_) => false,
+                };
+                let retry = copy_what(&what);
+                match MessageIterator::new(discord, channel, what, count).collect() {
+                    Ok(messages) => {
+                        let reached_start = backwards && messages.len() < count;
+                        ui_sender.send(MsgFromDiscord::History(channel, messages, reached_start));
                     },
-                    _ => {}
+                    Err(error) => fail(MsgToDiscord::GetMessages(channel, retry, count), error),
                 }
             },
         }
     }
 }
+/// Servers that are still unavailable in READY show up later as ServerCreate
+fn send_live_servers(servers: Vec<PossibleServer<LiveServer>>, ui_sender: &Sender<MsgFromDiscord>) {
+    for server in servers {
+        if let PossibleServer::Online(server) = server {
+            ui_sender.send(MsgFromDiscord::LiveServer(server));
+        }
+    }
+}
+
+fn handle_event(event: Event, ui_sender: &Sender<MsgFromDiscord>) {
+    match event {
+        Event::ServerCreate(PossibleServer::Online(server)) => {
+            ui_sender.send(MsgFromDiscord::LiveServer(server));
+        },
+        Event::MessageAck { channel_id, message_id } => {
+            ui_sender.send(MsgFromDiscord::Read(channel_id, message_id));
+        },
+        Event::ServerMemberUpdate { server_id, roles, user, nick } => {
+            ui_sender.send(MsgFromDiscord::MemberUpdate { server_id, user, roles, nick });
+        },
+        Event::ServerMemberAdd(server_id, member) => {
+            ui_sender.send(MsgFromDiscord::Member(server_id, member));
+        },
+        Event::ServerMembersChunk(server_id, members) => {
+            for member in members {
+                ui_sender.send(MsgFromDiscord::Member(server_id, member));
+            }
+        },
+        Event::ServerMemberRemove(server_id, user) => {
+            ui_sender.send(MsgFromDiscord::MemberRemove(server_id, user.id));
+        },
+        Event::ServerRoleCreate(server_id, role) | Event::ServerRoleUpdate(server_id, role) => {
+            ui_sender.send(MsgFromDiscord::RoleUpdate(server_id, role));
+        },
+        Event::ServerRoleDelete(server_id, role_id) => {
+            ui_sender.send(MsgFromDiscord::RoleDelete(server_id, role_id));
+        },
+        Event::MessageCreate(msg) => {
+            ui_sender.send(MsgFromDiscord::ChatMsg(msg));
+        },
+        Event::MessageUpdate { id, channel_id, content, edited_timestamp, .. } => {
+            ui_sender.send(MsgFromDiscord::MessageUpdate {
+                channel_id,
+                id,
+                content,
+                edited_timestamp,
+            });
+        },
+        Event::MessageDelete { channel_id, message_id } => {
+            ui_sender.send(MsgFromDiscord::MessageDelete(channel_id, message_id));
+        },
+        Event::MessageDeleteBulk { channel_id, ids } => {
+            ui_sender.send(MsgFromDiscord::MessageDeleteBulk(channel_id, ids));
+        },
+        Event::ChannelCreate(channel) => match channel {
+            Channel::Private(_) | Channel::Group(_) => {
+                ui_sender.send(MsgFromDiscord::PrivateChannels(vec![channel]));
+            },
+            _ => {}
+        },
+        _ => {}
+    }
+}
+
+/// Pages through a channel's history, at most 100 messages per request,
+/// moving away from the starting point until `total_desired` are fetched.
 struct MessageIterator<'a> {
-    last: Option<MessageId>,
+    what: Option<GetMessages>,
     client: &'a Discord,
     channelid: ChannelId,
     total_desired: usize,
 }
 impl<'a> MessageIterator<'a> {
-    fn new(client: &'a Discord, channelid: ChannelId, count: usize) -> MessageIterator<'a> {
+    fn new(client: &'a Discord, channelid: ChannelId, what: GetMessages, count: usize) -> MessageIterator<'a> {
         MessageIterator {
-            last: None,
+            what: Some(what),
             client,
             channelid,
             total_desired: count,
         }
     }
-    /// Flattens and collects the iterator of Vecs into a single Vec
-    fn collect(self) -> Vec<Message> {
+    /// Flattens and collects the pages into a single Vec, stopping at the first error
+    fn collect(self) -> discord::Result<Vec<Message>> {
         // self is moved in to consume the iterator into a list
-        self.flat_map(|x| x).collect()
+        let mut messages = vec![];
+        for page in self {
+            messages.extend(page?);
+        }
+        Ok(messages)
     }
 }
 impl<'a> Iterator for MessageIterator<'a> {
-    type Item = Vec<Message>;
+    type Item = discord::Result<Vec<Message>>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.total_desired <= 0 {
+        if self.total_desired == 0 {
             return None;
         }
-        let what = match self.last {
-            Some(id) => GetMessages::Before(id),
-            None => GetMessages::MostRecent,
+        let what = match self.what.take() {
+            Some(what) => what,
+            None => return None,
         };
         let limit = min(self.total_desired, 100); // API is limited to 100
+        let forward = match what {
+            GetMessages::After(_) => true,
+            _ => false,
+        };
 
-        println!("Getting {} messages from {}", limit, self.channelid);
-        let messages = self.client
-            .get_messages(self.channelid, what, Some(limit as u64))
-            .expect("Failed to get messages.");
+        let messages = match self.client.get_messages(self.channelid, what, Some(limit as u64)) {
+            Ok(messages) => messages,
+            Err(error) => {
+                self.total_desired = 0;
+                return Some(Err(error));
+            }
+        };
 
         if messages.len() < limit {
-           self.total_desired = 0; 
+            self.total_desired = 0;
         } else {
             self.total_desired -= messages.len();
         }
 
-        self.last = Some(messages.last().unwrap().id);
+        // Keep walking in the same direction we started in
+        self.what = if forward {
+            messages.iter().map(|m| m.id).max().map(GetMessages::After)
+        } else {
+            messages.iter().map(|m| m.id).min().map(GetMessages::Before)
+        };
 
-        Some(messages)
+        Some(Ok(messages))
     }
 }
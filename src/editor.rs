@@ -0,0 +1,415 @@
+use termion::event::Key;
+use unicode_segmentation::UnicodeSegmentation;
+
+use std::cmp::max;
+
+use wrap;
+
+/// A name a completion put in, and what it's sent as. Only these get encoded,
+/// so typing @someone by hand sends exactly that.
+#[derive(Debug, Clone)]
+pub struct Completed {
+    pub start: usize, // byte offsets into the text
+    pub end: usize,
+    pub wire: String,
+}
+
+/// What was typed, with its completions, put aside while the input is used for something else
+#[derive(Debug, Clone, Default)]
+pub struct Draft {
+    text: String,
+    completed: Vec<Completed>,
+}
+
+impl Draft {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+}
+
+impl From<String> for Draft {
+    fn from(text: String) -> Self {
+        Draft { text, completed: vec![] }
+    }
+}
+
+/// The message input: a string with a cursor that only ever sits between graphemes,
+/// plus a yank buffer for whatever the kill commands removed last. It can hold
+/// several lines, movement and kills work on the line the cursor is on.
+pub struct LineEditor {
+    text: String,
+    cursor: usize, // byte offset into `text`
+    yanked: String,
+    completed: Vec<Completed>, // sorted, never overlapping
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        LineEditor {
+            text: String::new(),
+            cursor: 0,
+            yanked: String::new(),
+            completed: vec![],
+        }
+    }
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+    /// Everything before the cursor
+    pub fn before_cursor(&self) -> &str {
+        &self.text[..self.cursor]
+    }
+    /// Swap out the text between `start` and the cursor, leaving the cursor after the new text
+    pub fn replace_back_to(&mut self, start: usize, text: &str) {
+        let cursor = self.cursor;
+        self.splice(start, cursor, text);
+        self.cursor = start + text.len();
+    }
+    /// Remember that `start..end` was put there by a completion and is sent as `wire`
+    pub fn mark(&mut self, start: usize, end: usize, wire: String) {
+        self.completed.retain(|span| span.end <= start || span.start >= end);
+        let at = self.completed.iter().position(|span| span.start > start).unwrap_or(self.completed.len());
+        self.completed.insert(at, Completed { start, end, wire });
+    }
+    pub fn completed(&self) -> &[Completed] {
+        &self.completed
+    }
+    pub fn line_count(&self) -> usize {
+        self.text.split('\n').count()
+    }
+    /// Replace the contents, leaving the cursor at the end
+    pub fn set(&mut self, text: String) {
+        self.set_draft(Draft::from(text));
+    }
+    /// Empty the line, handing back what was in it
+    pub fn take(&mut self) -> String {
+        self.take_draft().text
+    }
+    /// Like `set`, keeping track of the draft's completions
+    pub fn set_draft(&mut self, draft: Draft) {
+        self.cursor = draft.text.len();
+        self.text = draft.text;
+        self.completed = draft.completed;
+    }
+    /// Like `take`, along with the completions
+    pub fn take_draft(&mut self) -> Draft {
+        self.cursor = 0;
+        Draft {
+            text: ::std::mem::replace(&mut self.text, String::new()),
+            completed: ::std::mem::replace(&mut self.completed, vec![]),
+        }
+    }
+    /// Put `text` in front of what's there, leaving the cursor at the end
+    pub fn prepend(&mut self, text: &str) {
+        self.splice(0, 0, text);
+        self.cursor = self.text.len();
+    }
+
+    /// Applies an editing key, returns false if it isn't one
+    pub fn handle_key(&mut self, key: Key) -> bool {
+        match key {
+            Key::Char(chr) => self.insert(chr),
+            // Terminals can't tell Shift-Enter from Enter, but Alt-Enter comes through
+            Key::Alt('\r') | Key::Alt('\n') => self.insert('\n'),
+            Key::Backspace => {
+                let (start, cursor) = (self.prev_grapheme(), self.cursor);
+                self.splice(start, cursor, "");
+                self.cursor = start;
+            },
+            Key::Delete | Key::Ctrl('d') => {
+                let (cursor, end) = (self.cursor, self.next_grapheme());
+                self.splice(cursor, end, "");
+            },
+            Key::Left | Key::Ctrl('b') => self.cursor = self.prev_grapheme(),
+            Key::Right | Key::Ctrl('f') => self.cursor = self.next_grapheme(),
+            Key::Alt('b') => self.cursor = self.prev_word(),
+            Key::Alt('f') => self.cursor = self.next_word(),
+            Key::Up => match self.prev_line() {
+                Some(cursor) => self.cursor = cursor,
+                None => return false,
+            },
+            Key::Down => match self.next_line() {
+                Some(cursor) => self.cursor = cursor,
+                None => return false,
+            },
+            Key::Home | Key::Ctrl('a') => self.cursor = self.line_start(),
+            Key::End | Key::Ctrl('e') => self.cursor = self.line_end(),
+            Key::Ctrl('w') => {
+                let (start, cursor) = (self.prev_word(), self.cursor);
+                self.kill(start, cursor);
+            },
+            Key::Alt('d') => {
+                let (cursor, end) = (self.cursor, self.next_word());
+                self.kill(cursor, end);
+            },
+            Key::Ctrl('u') => {
+                let (start, cursor) = (self.line_start(), self.cursor);
+                self.kill(start, cursor);
+            },
+            Key::Ctrl('k') => {
+                let (cursor, end) = (self.cursor, self.line_end());
+                // At the end of a line, kill the line break like readline does
+                let end = if cursor == end { self.next_grapheme() } else { end };
+                self.kill(cursor, end);
+            },
+            Key::Ctrl('y') => {
+                let (cursor, yanked) = (self.cursor, self.yanked.clone());
+                self.splice(cursor, cursor, &yanked);
+                self.cursor += yanked.len();
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    fn insert(&mut self, chr: char) {
+        let cursor = self.cursor;
+        self.splice(cursor, cursor, chr.encode_utf8(&mut [0; 4]));
+        self.cursor += chr.len_utf8();
+    }
+    fn kill(&mut self, start: usize, end: usize) {
+        if start < end {
+            self.yanked = String::from(&self.text[start..end]);
+            self.splice(start, end, "");
+            self.cursor = start;
+        }
+    }
+    /// Replace `start..end` with `text`. Completions after it move along, ones
+    /// it touches are forgotten since they no longer say the name they stood for.
+    fn splice(&mut self, start: usize, end: usize, text: &str) {
+        self.text.drain(start..end);
+        self.text.insert_str(start, text);
+        let inserted = text.len();
+        self.completed.retain(|span| {
+            let before = span.end < start || (span.end == start && inserted == 0);
+            before || span.start >= end
+        });
+        for span in self.completed.iter_mut().filter(|span| span.start >= end) {
+            span.start = span.start + inserted - (end - start);
+            span.end = span.end + inserted - (end - start);
+        }
+    }
+
+    fn prev_grapheme(&self) -> usize {
+        UnicodeSegmentation::grapheme_indices(&self.text[..self.cursor], true)
+            .last()
+            .map_or(0, |(i, _)| i)
+    }
+    fn next_grapheme(&self) -> usize {
+        UnicodeSegmentation::graphemes(&self.text[self.cursor..], true)
+            .next()
+            .map_or(self.cursor, |grapheme| self.cursor + grapheme.len())
+    }
+    /// Start of the word before the cursor, skipping any whitespace in between
+    fn prev_word(&self) -> usize {
+        UnicodeSegmentation::split_word_bound_indices(&self.text[..self.cursor])
+            .rev()
+            .find(|&(_, word)| !word.trim().is_empty())
+            .map_or(0, |(i, _)| i)
+    }
+    /// End of the word after the cursor, skipping any whitespace in between
+    fn next_word(&self) -> usize {
+        UnicodeSegmentation::split_word_bound_indices(&self.text[self.cursor..])
+            .find(|&(_, word)| !word.trim().is_empty())
+            .map_or(self.text.len(), |(i, word)| self.cursor + i + word.len())
+    }
+
+    fn line_start(&self) -> usize {
+        self.text[..self.cursor].rfind('\n').map_or(0, |i| i + 1)
+    }
+    fn line_end(&self) -> usize {
+        self.text[self.cursor..].find('\n').map_or(self.text.len(), |i| self.cursor + i)
+    }
+    /// Same column on the line above, or as close as that line allows
+    fn prev_line(&self) -> Option<usize> {
+        let start = self.line_start();
+        if start == 0 {
+            return None;
+        }
+        let above = self.text[..start - 1].rfind('\n').map_or(0, |i| i + 1);
+        let column = wrap::width(&self.text[start..self.cursor]);
+        Some(above + at_column(&self.text[above..start - 1], column))
+    }
+    fn next_line(&self) -> Option<usize> {
+        let end = self.line_end();
+        if end == self.text.len() {
+            return None;
+        }
+        let below = end + 1;
+        let below_end = self.text[below..].find('\n').map_or(self.text.len(), |i| below + i);
+        let column = wrap::width(&self.text[self.line_start()..self.cursor]);
+        Some(below + at_column(&self.text[below..below_end], column))
+    }
+
+    /// The rows that fit in a `width` x `height` box with the cursor in it, and
+    /// the cursor's column and row within that box
+    pub fn view(&self, width: usize, height: usize) -> (Vec<&str>, (usize, usize)) {
+        let row = self.text[..self.cursor].matches('\n').count();
+        let start = self.line_start();
+        let column = wrap::width(&self.text[start..self.cursor]);
+        // A squashed terminal can leave no room at all, still show the cursor's line
+        let width = max(width, 1);
+        let height = max(height, 1);
+
+        // Scroll so the cursor fits, with a cell to spare for it
+        let first = if row >= height { row + 1 - height } else { 0 };
+        let shift = if column >= width { column + 1 - width } else { 0 };
+        let rows = self.text
+            .split('\n')
+            .skip(first)
+            .take(height)
+            .map(|line| &line[at_column(line, shift)..])
+            .collect();
+        let skipped = wrap::width(&self.text[start..start + at_column(&self.text[start..], shift)]);
+        (rows, (column.checked_sub(skipped).unwrap_or(0), row - first))
+    }
+}
+
+/// Byte offset of the first grapheme in `line` that starts at or past `column`
+fn at_column(line: &str, column: usize) -> usize {
+    let mut width = 0;
+    for (i, grapheme) in UnicodeSegmentation::grapheme_indices(line, true) {
+        if width >= column {
+            return i;
+        }
+        width += wrap::width(grapheme);
+    }
+    line.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(editor: &mut LineEditor, keys: &[Key]) {
+        for &key in keys {
+            editor.handle_key(key);
+        }
+    }
+
+    fn typed(text: &str) -> LineEditor {
+        let mut editor = LineEditor::new();
+        for c in text.chars() {
+            editor.handle_key(Key::Char(c));
+        }
+        editor
+    }
+
+    /// "say @anna " with @anna completed, cursor at the end
+    fn with_completion() -> LineEditor {
+        let mut editor = typed("say @an");
+        editor.replace_back_to(4, "@anna ");
+        editor.mark(4, 9, String::from("<@1>"));
+        editor
+    }
+
+    fn spans(editor: &LineEditor) -> Vec<(usize, usize, &str)> {
+        editor.completed().iter().map(|span| (span.start, span.end, &span.wire[..])).collect()
+    }
+
+    #[test]
+    fn typing_after_a_completion_keeps_it() {
+        let mut editor = with_completion();
+        press(&mut editor, &[Key::Char('h'), Key::Char('i')]);
+        assert_eq!(editor.text(), "say @anna hi");
+        assert_eq!(spans(&editor), vec![(4, 9, "<@1>")]);
+    }
+
+    #[test]
+    fn typing_before_a_completion_moves_it() {
+        let mut editor = with_completion();
+        press(&mut editor, &[Key::Home, Key::Char('é')]);
+        assert_eq!(editor.text(), "ésay @anna ");
+        assert_eq!(spans(&editor), vec![(6, 11, "<@1>")]);
+        assert_eq!(&editor.text()[6..11], "@anna");
+    }
+
+    #[test]
+    fn typing_inside_or_against_a_completion_forgets_it() {
+        let mut editor = with_completion();
+        press(&mut editor, &[Key::Left, Key::Left, Key::Left, Key::Char('z')]);
+        assert_eq!(editor.text(), "say @anzna ");
+        assert!(spans(&editor).is_empty());
+
+        let mut editor = with_completion();
+        press(&mut editor, &[Key::Left, Key::Char('z')]);
+        assert_eq!(editor.text(), "say @annaz ");
+        assert!(spans(&editor).is_empty());
+    }
+
+    #[test]
+    fn deleting_the_space_after_a_completion_keeps_it() {
+        let mut editor = with_completion();
+        press(&mut editor, &[Key::Backspace]);
+        assert_eq!(editor.text(), "say @anna");
+        assert_eq!(spans(&editor), vec![(4, 9, "<@1>")]);
+    }
+
+    #[test]
+    fn killing_before_a_completion_moves_it() {
+        let mut editor = with_completion();
+        press(&mut editor, &[Key::Home, Key::Alt('d')]);
+        assert_eq!(editor.text(), " @anna ");
+        assert_eq!(spans(&editor), vec![(1, 6, "<@1>")]);
+    }
+
+    #[test]
+    fn killing_after_a_completion_keeps_it() {
+        let mut editor = with_completion();
+        press(&mut editor, &[Key::Char('h'), Key::Char('i'), Key::Ctrl('w')]);
+        assert_eq!(editor.text(), "say @anna ");
+        assert_eq!(spans(&editor), vec![(4, 9, "<@1>")]);
+    }
+
+    #[test]
+    fn killing_a_completion_forgets_it_and_yanking_brings_back_only_text() {
+        let mut editor = with_completion();
+        press(&mut editor, &[Key::Ctrl('u')]);
+        assert_eq!(editor.text(), "");
+        assert!(spans(&editor).is_empty());
+        press(&mut editor, &[Key::Ctrl('y')]);
+        assert_eq!(editor.text(), "say @anna ");
+        assert!(spans(&editor).is_empty());
+    }
+
+    #[test]
+    fn cycling_candidates_replaces_the_mark() {
+        let mut editor = with_completion();
+        editor.replace_back_to(4, "@annabel ");
+        assert!(spans(&editor).is_empty());
+        editor.mark(4, 12, String::from("<@2>"));
+        assert_eq!(spans(&editor), vec![(4, 12, "<@2>")]);
+    }
+
+    #[test]
+    fn drafts_keep_their_completions() {
+        let mut editor = with_completion();
+        let draft = editor.take_draft();
+        assert!(editor.is_empty() && spans(&editor).is_empty());
+        editor.set_draft(draft);
+        assert_eq!(editor.text(), "say @anna ");
+        assert_eq!(spans(&editor), vec![(4, 9, "<@1>")]);
+    }
+
+    #[test]
+    fn quoting_moves_completions_along() {
+        let mut editor = with_completion();
+        editor.prepend("> hi\n");
+        assert_eq!(spans(&editor), vec![(9, 14, "<@1>")]);
+        assert_eq!(&editor.text()[9..14], "@anna");
+    }
+
+    #[test]
+    fn view_with_no_room_still_has_the_cursor_line() {
+        let editor = typed("one\ntwo");
+        let (rows, cursor) = editor.view(0, 0);
+        assert_eq!(rows, vec![""]);
+        assert_eq!(cursor, (0, 0));
+    }
+}
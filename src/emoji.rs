@@ -0,0 +1,72 @@
+/// Shortcodes for the emoji people reach for most, as Discord names them
+pub static SHORTCODES: &'static [(&'static str, &'static str)] = &[
+    ("+1", "👍"),
+    ("-1", "👎"),
+    ("100", "💯"),
+    ("angry", "😠"),
+    ("blush", "😊"),
+    ("boom", "💥"),
+    ("broken_heart", "💔"),
+    ("bug", "🐛"),
+    ("clap", "👏"),
+    ("confused", "😕"),
+    ("cry", "😢"),
+    ("crab", "🦀"),
+    ("eyes", "👀"),
+    ("fire", "🔥"),
+    ("flushed", "😳"),
+    ("frowning", "😦"),
+    ("ghost", "👻"),
+    ("grimacing", "😬"),
+    ("grin", "😁"),
+    ("grinning", "😀"),
+    ("heart", "❤️"),
+    ("heart_eyes", "😍"),
+    ("hugging", "🤗"),
+    ("innocent", "😇"),
+    ("joy", "😂"),
+    ("kissing_heart", "😘"),
+    ("laughing", "😆"),
+    ("man_shrugging", "🤷‍♂️"),
+    ("neutral_face", "😐"),
+    ("ok_hand", "👌"),
+    ("open_mouth", "😮"),
+    ("partying_face", "🥳"),
+    ("pensive", "😔"),
+    ("pray", "🙏"),
+    ("rage", "😡"),
+    ("raised_hands", "🙌"),
+    ("relieved", "😌"),
+    ("rocket", "🚀"),
+    ("rofl", "🤣"),
+    ("scream", "😱"),
+    ("see_no_evil", "🙈"),
+    ("shrug", "🤷"),
+    ("skull", "💀"),
+    ("sleeping", "😴"),
+    ("slight_smile", "🙂"),
+    ("smile", "😄"),
+    ("smiley", "😃"),
+    ("smirk", "😏"),
+    ("sob", "😭"),
+    ("sparkles", "✨"),
+    ("star", "⭐"),
+    ("sunglasses", "😎"),
+    ("sweat_smile", "😅"),
+    ("tada", "🎉"),
+    ("thinking", "🤔"),
+    ("thumbsdown", "👎"),
+    ("thumbsup", "👍"),
+    ("tired_face", "😫"),
+    ("upside_down", "🙃"),
+    ("warning", "⚠️"),
+    ("wave", "👋"),
+    ("weary", "😩"),
+    ("white_check_mark", "✅"),
+    ("wink", "😉"),
+    ("woman_shrugging", "🤷‍♀️"),
+    ("x", "❌"),
+    ("yum", "😋"),
+    ("zany_face", "🤪"),
+    ("zipper_mouth", "🤐"),
+];
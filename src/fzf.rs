@@ -0,0 +1,70 @@
+use discord::model::ChannelId;
+
+use editor::LineEditor;
+
+/// How many recently opened channels get a boost, and how much the last one gets
+const RECENT: usize = 10;
+const RECENT_BONUS: i64 = 3;
+
+/// Somewhere the quick-switcher can jump to
+pub struct Target {
+    pub label: String,
+    pub id: ChannelId,
+    pub server: usize,
+    pub channel: usize,
+}
+
+/// The quick-switcher's query and which of the ranked targets is highlighted
+pub struct Switcher {
+    pub query: LineEditor,
+    pub selected: usize,
+}
+
+impl Switcher {
+    pub fn new() -> Self {
+        Switcher {
+            query: LineEditor::new(),
+            selected: 0,
+        }
+    }
+}
+
+/// Matches `pattern` as a subsequence of `candidate`, ignoring case. Runs of
+/// consecutive letters and letters that start a word score higher, gaps cost.
+pub fn score(pattern: &str, candidate: &str) -> Option<i64> {
+    let candidate: Vec<char> = candidate.to_lowercase().chars().collect();
+    let mut score = 0;
+    let mut from = 0;
+    let mut previous: Option<usize> = None;
+    for p in pattern.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = match (from..candidate.len()).find(|&i| candidate[i] == p) {
+            Some(found) => found,
+            None => return None,
+        };
+        score += 1;
+        if previous.map_or(false, |previous| previous + 1 == found) {
+            score += 5;
+        }
+        if found == 0 || !candidate[found - 1].is_alphanumeric() {
+            score += 8;
+        }
+        score -= (found - from) as i64;
+        previous = Some(found);
+        from = found + 1;
+    }
+    Some(score)
+}
+
+/// Best matches first, with channels used lately pulled up. `recent` is most recent first.
+pub fn rank(targets: Vec<Target>, query: &str, recent: &[ChannelId]) -> Vec<Target> {
+    let mut scored: Vec<(i64, Target)> = targets.into_iter()
+        .filter_map(|target| {
+            let bonus = recent.iter().take(RECENT).position(|&id| id == target.id)
+                .map_or(0, |position| (RECENT - position) as i64 * RECENT_BONUS);
+            score(query, &target.label).map(|score| (score + bonus, target))
+        })
+        .collect();
+    // Stable, so equal scores keep the server list order
+    scored.sort_by(|a, b| b.0.cmp(&a.0));
+    scored.into_iter().map(|(_, target)| target).collect()
+}
@@ -0,0 +1,87 @@
+use discord::model::ChannelId;
+use serde_json;
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions, Permissions};
+use std::io::{Read, Write};
+use std::io;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+const PATH: &'static str = "./history";
+/// Oldest entries get dropped past this many per channel
+const MAX_ENTRIES: usize = 100;
+
+/// Messages sent from this client, per channel, oldest first. Saved to
+/// `./history` as JSON so they're still there next time, readable only by us
+/// since DMs end up in there too.
+pub struct History {
+    sent: HashMap<ChannelId, Vec<String>>,
+    persist: bool, // false for --mock, whose channels aren't real
+}
+
+impl History {
+    /// Starts out empty if there's no history file yet, or with a complaint if it's unreadable
+    pub fn load() -> (Self, Option<String>) {
+        let mut history = History { sent: HashMap::new(), persist: true };
+        let mut data = String::new();
+        match File::open(PATH) {
+            Ok(mut f) => {
+                if let Err(error) = f.read_to_string(&mut data) {
+                    return (history, Some(format!("Couldn't read history: {}", error)));
+                }
+            },
+            Err(_) => return (history, None),
+        }
+        // Ids are the keys, and JSON keys are strings
+        let parsed: HashMap<String, Vec<String>> = match serde_json::from_str(&data) {
+            Ok(parsed) => parsed,
+            Err(error) => return (history, Some(format!("Couldn't parse history: {}", error))),
+        };
+        for (id, entries) in parsed {
+            if let Ok(id) = id.parse() {
+                history.sent.insert(ChannelId(id), entries);
+            }
+        }
+        (history, None)
+    }
+
+    /// Kept for this run only, the history file is left alone
+    pub fn in_memory() -> Self {
+        History { sent: HashMap::new(), persist: false }
+    }
+
+    pub fn entries(&self, channel: ChannelId) -> &[String] {
+        self.sent.get(&channel).map_or(&[], |entries| &entries[..])
+    }
+
+    /// Remember a sent message and write the whole history back out
+    pub fn push(&mut self, channel: ChannelId, text: String) -> io::Result<()> {
+        {
+            let entries = self.sent.entry(channel).or_insert_with(Vec::new);
+            // Sending the same thing twice in a row only needs recalling once
+            if entries.last() != Some(&text) {
+                entries.push(text);
+            }
+            if entries.len() > MAX_ENTRIES {
+                let extra = entries.len() - MAX_ENTRIES;
+                entries.drain(..extra);
+            }
+        }
+        self.save()
+    }
+
+    fn save(&self) -> io::Result<()> {
+        if !self.persist {
+            return Ok(());
+        }
+        let by_id: HashMap<String, &Vec<String>> = self.sent.iter()
+            .map(|(id, entries)| (id.0.to_string(), entries))
+            .collect();
+        let data = serde_json::to_string(&by_id)
+            .map_err(|error| io::Error::new(io::ErrorKind::Other, error))?;
+        let mut file = OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(PATH)?;
+        // The mode only counts when the file is new, older ones may be world-readable
+        file.set_permissions(Permissions::from_mode(0o600))?;
+        file.write_all(data.as_bytes())
+    }
+}
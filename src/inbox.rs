@@ -0,0 +1,62 @@
+use discord::model::{ChannelId, Message, MessageId};
+
+use std::cmp::{max, min};
+
+/// Past this many the oldest mentions get dropped
+const MAX_MENTIONS: usize = 200;
+
+/// A message that pinged us, and where it was said. Names are resolved against
+/// the server it came from when it's added, the inbox shows all servers at once.
+pub struct Mention {
+    pub message: Message,
+    pub place: String, // "Server #channel", or "@name" for a DM
+    pub author: String, // nickname there, if they have one
+    pub text: String, // the content as plain text
+}
+
+/// Every message that mentioned us since startup, newest first, and which one is highlighted
+pub struct Inbox {
+    pub mentions: Vec<Mention>,
+    pub selected: usize,
+}
+
+impl Inbox {
+    pub fn new() -> Self {
+        Inbox {
+            mentions: vec![],
+            selected: 0,
+        }
+    }
+
+    /// Adds a mention unless it's already here, history pages overlap with live messages
+    pub fn add(&mut self, message: Message, place: String, author: String, text: String) {
+        if self.mentions.iter().any(|mention| mention.message.id == message.id) {
+            return;
+        }
+        let at = self.mentions.iter().position(|mention| mention.message.id < message.id)
+            .unwrap_or(self.mentions.len());
+        self.mentions.insert(at, Mention { message, place, author, text });
+        self.mentions.truncate(MAX_MENTIONS);
+    }
+    pub fn edit(&mut self, id: MessageId, content: String, text: String) {
+        if let Some(mention) = self.mentions.iter_mut().find(|mention| mention.message.id == id) {
+            mention.message.content = content;
+            mention.text = text;
+        }
+    }
+    pub fn remove(&mut self, channel_id: ChannelId, ids: &[MessageId]) {
+        self.mentions.retain(|mention| mention.message.channel_id != channel_id || !ids.contains(&mention.message.id));
+        self.selected = min(self.selected, self.mentions.len().checked_sub(1).unwrap_or(0));
+    }
+    pub fn select(&mut self, delta: isize) {
+        let last = self.mentions.len().checked_sub(1).unwrap_or(0) as isize;
+        self.selected = max(0, min(self.selected as isize + delta, last)) as usize;
+    }
+
+    /// One row per mention: where, who, and the first line of what they said
+    pub fn lines(&self) -> Vec<String> {
+        self.mentions.iter().map(|mention| {
+            format!("{}  {}: {}", mention.place, mention.author, mention.text.lines().next().unwrap_or(""))
+        }).collect()
+    }
+}
@@ -5,20 +5,34 @@ extern crate tui;
 extern crate discord;
 extern crate rpassword;
 extern crate itertools;
+extern crate chrono;
+#[macro_use]
+extern crate serde_json;
+extern crate base64;
+extern crate unicode_segmentation;
+extern crate unicode_width;
 
 use std::thread;
 use std::sync::{Arc, Mutex};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
 
 use rpassword::read_password;
 use std::vec::Vec;
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
 use std::io;
+use std::process::{Command, Stdio};
+use std::os::unix::fs::OpenOptionsExt;
 use std::time;
 use std::cmp::{max, min};
 
+use itertools::Itertools;
+
 use discord::model::Message;
-use discord::Discord;
+use discord::{Discord, GetMessages};
+
+use chrono::{DateTime, FixedOffset};
 
 use termion::event;
 use termion::event::Key;
@@ -33,9 +47,54 @@ use tui::style::{Color, Modifier, Style};
 mod chatwidget;
 use chatwidget::ChatWidget;
 
+mod markdown;
+mod clock;
+mod wrap;
+mod mentions;
+use mentions::Names;
+
 mod discord_provider;
-use discord_provider::{DiscordProvider, MsgToDiscord, MsgFromDiscord};
+use discord_provider::{ChatBackend, ConnectionState, DiscordProvider, MsgToDiscord, MsgFromDiscord, ProviderError};
+
+mod mock_provider;
+use mock_provider::MockProvider;
+
+mod popup;
+use popup::Popup;
+
+mod editor;
+use editor::{Draft, LineEditor};
+
+mod config;
+use config::Config;
+
+mod history;
+use history::History;
+
+mod emoji;
+mod complete;
+use complete::{Completion, Entry};
+
+mod commands;
+
+mod fzf;
+use fzf::{Switcher, Target};
+
+mod inbox;
+use inbox::Inbox;
+
+mod notify;
+
+
+/// The "Direct Messages" entry in the server list isn't a real server
+const DM_SERVER: discord::model::ServerId = discord::model::ServerId(0);
 
+/// How many messages to fetch when a channel is first opened
+const HISTORY_PAGE: usize = 50;
+/// Most messages to catch up on per channel after a reconnect
+const BACKFILL_LIMIT: usize = 200;
+/// Read markers are sent at most this often, a busy channel would otherwise ack every message
+const ACK_DELAY_SECS: u64 = 3;
 
 static selectedBorder: Style = Style {
     fg: Color::Green,
@@ -48,9 +107,10 @@ static defaultBorder: Style = Style {
     modifier: Modifier::Reset
 };
 
-struct MockMessage {
-    username: String,
-    content: String,
+/// A line for the notice area under the chat, e.g. why a message wasn't sent
+struct Notice {
+    text: String,
+    error: bool,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -59,20 +119,48 @@ enum Mode {
     TextInput,
     ChannelSelect,
     ServerSelect,
+    MessageSelect,
+    ConfirmDelete,
     Command,
     Fzf,
+    Mentions,
     Exiting
 }
 struct AppState {
     size: Rect,
-    messages: Vec<MockMessage>,
-    content: String,
-    offset: usize,
+    notices: Vec<Notice>,
+    input: LineEditor,
+    draft_channel: Option<discord::model::ChannelId>, // whose draft is in the input
+    draft_parked: bool, // the draft is in its channel while the input is used for something else
+    history: History,
+    browsing: Option<(usize, Draft)>, // how far back in history we are, and the draft from before
+    completion: Option<Completion>, // Tab cycles through these until some other key is pressed
+    switcher: Switcher,
+    inbox: Inbox,
+    pending_jump: Option<discord::model::MessageId>, // select this once it's loaded in the active channel
+    dnd: bool, // do not disturb, no notifications at all
+    members_requested: HashSet<(discord::model::ServerId, discord::model::UserId)>, // asked for once, found or not
+    urgent: bool, // the window title says something needs attention
+    recent_channels: Vec<discord::model::ChannelId>, // most recently opened first
+    read_state: HashMap<discord::model::ChannelId, discord::model::ReadState>, // from READY, for channels we haven't made yet
+    pending_acks: HashMap<discord::model::ChannelId, discord::model::MessageId>, // read here, not told to Discord yet
+    acks_due: Option<time::Instant>,
+    cursor: Cell<Option<(u16, u16)>>, // where the input line wants the terminal cursor, set on draw
     scroll_pos: usize,
+    chat_size: Cell<(usize, usize)>, // width and height of the message list, updated on every draw
     servers: Vec<Server>,
+    live_servers: HashMap<discord::model::ServerId, discord::model::LiveServer>, // members, roles, emojis
     active_server: usize,
     mode: Mode,
     mode_stack: Vec<Mode>,
+    connection: ConnectionState,
+    me: Option<discord::model::UserId>,
+    selected: Option<discord::model::MessageId>, // highlighted in the chat
+    editing: Option<(discord::model::ChannelId, discord::model::MessageId)>,
+    reacting: Option<(discord::model::ChannelId, discord::model::MessageId)>,
+    show_details: bool,
+    needs_clear: bool, // something else drew over the screen, repaint all of it
+    config: Config,
     to_provider: chan::Sender<MsgToDiscord>,
     from_provider: chan::Receiver<MsgFromDiscord>,
 }
@@ -84,6 +172,37 @@ struct Server {
     server_info: discord::model::ServerInfo,
 }
 
+impl Server {
+    fn new(server_info: discord::model::ServerInfo) -> Self {
+        Server {
+            channels: Vec::new(),
+            active_channel: 0,
+            server_info,
+        }
+    }
+    /// Pseudo-server holding DMs and group DMs
+    fn direct_messages() -> Self {
+        Server::new(discord::model::ServerInfo {
+            id: DM_SERVER,
+            name: String::from("Direct Messages"),
+            icon: None,
+            owner: false,
+            permissions: discord::model::permissions::Permissions::empty(),
+        })
+    }
+    fn is_private(&self) -> bool {
+        self.server_info.id == DM_SERVER
+    }
+    /// Most recently active conversation first, like Discord's own DM list
+    fn sort_by_activity(&mut self) {
+        let active_id = self.channels.get(self.active_channel).map(|channel| channel.id);
+        self.channels.sort_by(|a, b| b.last_message_id.cmp(&a.last_message_id));
+        if let Some(id) = active_id {
+            self.active_channel = self.channels.iter().position(|channel| channel.id == id).unwrap_or(0);
+        }
+    }
+}
+
 impl AsRef<str> for Server {
     fn as_ref(&self) -> &str {
        &self.server_info.name
@@ -94,9 +213,57 @@ struct Channel {
     name: String,
     id: discord::model::ChannelId,
     messages: Vec<discord::model::Message>,
+    history_requested: bool,
+    loading_older: bool,
+    reached_start: bool,
+    last_message_id: Option<discord::model::MessageId>,
+    draft: Draft, // what was typed here, while some other channel has the input
+    topic: Option<String>,
+    unread: usize, // messages that came in while we were elsewhere
+    mentions: usize,
+    last_read: Option<discord::model::MessageId>,
+    divider: Option<discord::model::MessageId>, // last_read as it was when we switched here
+    gap_after: Option<discord::model::MessageId>, // after a jump, what comes after this isn't loaded yet
+    backfilling: bool, // a page for the gap is on its way
 }
 
 impl Channel {
+    fn new(id: discord::model::ChannelId, name: String) -> Self {
+        Channel {
+            name,
+            id,
+            messages: vec![],
+            history_requested: false,
+            loading_older: false,
+            reached_start: false,
+            last_message_id: None,
+            draft: Draft::default(),
+            topic: None,
+            unread: 0,
+            mentions: 0,
+            last_read: None,
+            divider: None,
+            gap_after: None,
+            backfilling: false,
+        }
+    }
+    /// DMs are named after the people in them, group DMs may have a name of their own
+    fn from_private(channel: &discord::model::Channel) -> Option<Self> {
+        let (id, name, last_message_id) = match channel {
+            &discord::model::Channel::Private(ref dm) => (dm.id, dm.recipient.name.clone(), dm.last_message_id),
+            &discord::model::Channel::Group(ref group) => {
+                let name = match group.name {
+                    Some(ref name) => name.clone(),
+                    None => group.recipients.iter().map(|user| &user.name[..]).join(", "),
+                };
+                (group.channel_id, name, group.last_message_id)
+            },
+            _ => return None,
+        };
+        let mut dm = Channel::new(id, name);
+        dm.last_message_id = last_message_id;
+        Some(dm)
+    }
     fn send_message(&self, discord_chan: &chan::Sender<MsgToDiscord>, content: String) {
         let message = MsgToDiscord::SendMessage(
             self.id,
@@ -104,6 +271,88 @@ impl Channel {
         );
         discord_chan.send(message);
     }
+    /// Ask for the most recent messages, once per channel
+    fn request_history(&mut self, discord_chan: &chan::Sender<MsgToDiscord>) {
+        if self.history_requested {
+            return;
+        }
+        self.history_requested = true;
+        discord_chan.send(MsgToDiscord::GetMessages(self.id, GetMessages::MostRecent, HISTORY_PAGE));
+    }
+    /// Ask for the page before the oldest loaded message, unless one is in flight
+    fn request_older(&mut self, discord_chan: &chan::Sender<MsgToDiscord>) {
+        if self.loading_older || self.reached_start || !self.history_requested {
+            return;
+        }
+        let oldest = match self.messages.first() {
+            Some(msg) => msg.id,
+            None => return,
+        };
+        self.loading_older = true;
+        discord_chan.send(MsgToDiscord::GetMessages(self.id, GetMessages::Before(oldest), HISTORY_PAGE));
+    }
+    /// Fetch whatever was said since the newest message we have. It's treated
+    /// as a gap, so if more was missed than one page holds the rest follows
+    /// the same way it does after a jump.
+    fn request_missed(&mut self, discord_chan: &chan::Sender<MsgToDiscord>) {
+        if self.gap_after.is_none() {
+            self.gap_after = self.messages.last().map(|msg| msg.id);
+        }
+        self.request_gap(discord_chan);
+    }
+    /// Fetch the next page of what's missing after a jump back in time
+    fn request_gap(&mut self, discord_chan: &chan::Sender<MsgToDiscord>) {
+        if let (Some(gap), false) = (self.gap_after, self.backfilling) {
+            self.backfilling = true;
+            discord_chan.send(MsgToDiscord::GetMessages(self.id, GetMessages::After(gap), BACKFILL_LIMIT));
+        }
+    }
+    /// Move the gap along as pages after it arrive, closing it once a page
+    /// comes up short or runs into messages we already had
+    fn fill_gap(&mut self, messages: &[Message], reached_start: bool) {
+        let gap = match self.gap_after {
+            Some(gap) => gap,
+            None => return,
+        };
+        let newest = messages.iter().map(|msg| msg.id).max();
+        let overlaps = messages.iter().any(|msg| msg.id > gap && self.messages.iter().any(|have| have.id == msg.id));
+        if self.backfilling && !reached_start && messages.iter().all(|msg| msg.id > gap) {
+            // The answer to request_gap
+            self.backfilling = false;
+            self.gap_after = if overlaps || messages.len() < BACKFILL_LIMIT { None } else { newest };
+        } else if overlaps {
+            self.gap_after = None;
+        } else if newest > Some(gap) {
+            // The page around the message we jumped to
+            self.gap_after = newest;
+        }
+    }
+    /// Merge fetched messages in, keeping snowflake order and dropping duplicates
+    fn merge_messages(&mut self, messages: Vec<Message>) {
+        self.messages.extend(messages);
+        self.messages.sort_by_key(|msg| msg.id);
+        self.messages.dedup_by_key(|msg| msg.id);
+    }
+}
+
+impl Channel {
+    /// Either something came in while we were away, or Discord says we're behind
+    fn is_unread(&self) -> bool {
+        self.unread > 0 || match (self.last_message_id, self.last_read) {
+            (Some(last), Some(read)) => last > read,
+            _ => false,
+        }
+    }
+    fn apply_read_state(&mut self, read_state: &HashMap<discord::model::ChannelId, discord::model::ReadState>) {
+        if let Some(state) = read_state.get(&self.id) {
+            self.last_read = state.last_message_id;
+            self.mentions = state.mention_count as usize;
+        }
+    }
+    /// The name without the " (kind)" we add for the channel list
+    fn bare_name(&self) -> &str {
+        self.name.rsplitn(2, " (").last().unwrap_or(&self.name)
+    }
 }
 
 impl AsRef<str> for Channel {
@@ -113,50 +362,72 @@ impl AsRef<str> for Channel {
 }
 
 impl AppState {
-    fn new( to_provider: chan::Sender<MsgToDiscord>, from_provider: chan::Receiver<MsgFromDiscord>) -> Self {
+    fn new( to_provider: chan::Sender<MsgToDiscord>, from_provider: chan::Receiver<MsgFromDiscord>, config: Config, history: History) -> Self {
         AppState {
             size: Rect::default(),
-            messages: vec![],
-            content: String::from(""),
-            offset: 0,
+            notices: vec![],
+            input: LineEditor::new(),
+            draft_channel: None,
+            draft_parked: false,
+            history,
+            browsing: None,
+            completion: None,
+            switcher: Switcher::new(),
+            inbox: Inbox::new(),
+            pending_jump: None,
+            dnd: config.dnd,
+            members_requested: HashSet::new(),
+            urgent: false,
+            recent_channels: vec![],
+            read_state: HashMap::new(),
+            pending_acks: HashMap::new(),
+            acks_due: None,
+            cursor: Cell::new(None),
             scroll_pos: 0,
+            chat_size: Cell::new((0, 0)),
             active_server: 0,
             servers: vec![],
+            live_servers: HashMap::new(),
             mode: Mode::Normal,
             mode_stack: vec![],
+            connection: ConnectionState::Offline,
+            me: None,
+            selected: None,
+            editing: None,
+            reacting: None,
+            show_details: false,
+            needs_clear: false,
+            config,
             to_provider,
             from_provider
         }
     }
-    fn add_character(&mut self, chr: char) {
-        let mut content_to_append = String::new();
-        content_to_append.push(chr);
-        let end = self.content.len();
-        self.content = format!("{}{}{}", &self.content[0..self.offset], content_to_append, &self.content[self.offset..end]);
-        self.offset = min(self.content.len(), self.offset + 1);
-    }
-    fn remove_character(&mut self) {
-        let n = self.content.len();
-
-        let left_bound = self.offset.checked_sub(1).unwrap_or(0);
-
-        let right_bound = min(n, self.offset + 1);
-
-        if (n != 0) {
-            self.content = format!("{}{}", &self.content[..left_bound], &self.content[self.offset..n]);
-            self.offset = left_bound;
-        }
-    }
-    fn send_message(&mut self, text: String) {
+    /// Sends `encoded`, and keeps the draft as it was typed for recalling later
+    fn send_message(&mut self, draft: Draft, encoded: String) {
         // self.to_provider.send(MsgToDiscord::Echo(self.content.clone()));
-        let provider = &self.to_provider;
-        let active_server = &self.servers[self.active_server];
-        let active_channel = &active_server.channels[active_server.active_channel];
-        active_channel.send_message(provider, text);
+        let sent = match self.active_channel_ref() {
+            Some(channel) => {
+                channel.send_message(&self.to_provider, encoded);
+                Some(channel.id)
+            },
+            None => None,
+        };
+        let channel_id = match sent {
+            Some(id) => id,
+            None => {
+                // A server with nothing in it, or Direct Messages without any DMs
+                self.input.set_draft(draft);
+                return self.notify_error(String::from("No channel to send to"));
+            },
+        };
+        if let Err(error) = self.history.push(channel_id, String::from(draft.text())) {
+            self.notify_error(format!("Couldn't save history: {}", error));
+        }
     }
     fn next_server(&mut self) {
         let new_index = (self.active_server + 1) % self.servers.len();
         self.active_server = new_index;
+        self.open_channel();
     }
     fn prev_server(&mut self) {
         if self.active_server > 0 {
@@ -164,6 +435,346 @@ impl AppState {
         } else {
             self.active_server = self.servers.len() - 1;
         }
+        self.open_channel();
+    }
+    fn next_channel(&mut self) {
+        self.active_server().next_channel();
+        self.open_channel();
+    }
+    fn prev_channel(&mut self) {
+        self.active_server().prev_channel();
+        self.open_channel();
+    }
+    /// Called whenever the active channel changes, loads its history if needed
+    fn open_channel(&mut self) {
+        self.scroll_pos = 0;
+        self.pending_jump = None;
+        // Swap the input over to this channel's draft
+        let active = self.active_channel_id();
+        if active.is_some() && active != self.recent_channels.first().cloned() {
+            self.place_divider();
+        }
+        if let Some(id) = active {
+            self.recent_channels.retain(|&recent| recent != id);
+            self.recent_channels.insert(0, id);
+            self.recent_channels.truncate(20);
+        }
+        if active != self.draft_channel && !self.draft_parked {
+            self.park_draft();
+            self.draft_channel = active;
+            self.restore_draft();
+        }
+        let provider = self.to_provider.clone();
+        {
+            let server = &mut self.servers[self.active_server];
+            if server.channels.len() > 0 {
+                server.active_channel().request_history(&provider);
+            }
+        }
+        // Whatever was read in the channel we left can go out now
+        self.send_acks();
+        self.mark_read();
+    }
+    /// Remember where we left off in the active channel before mark_read moves past it
+    fn place_divider(&mut self) {
+        let server = &mut self.servers[self.active_server];
+        let index = server.active_channel;
+        if let Some(channel) = server.channels.get_mut(index) {
+            channel.divider = if !channel.is_unread() {
+                None
+            } else if channel.last_read.is_some() {
+                channel.last_read
+            } else {
+                // Never read, all we know is how many came in
+                let n = channel.messages.len();
+                Some(match n.checked_sub(channel.unread + 1) {
+                    Some(i) => channel.messages[i].id,
+                    None => discord::model::MessageId(0),
+                })
+            };
+        }
+    }
+    /// Go to the next channel with something new in it, pings first
+    fn jump_to_unread(&mut self) {
+        let active = self.active_channel_id();
+        let mut found = None;
+        for &pinged in [true, false].iter() {
+            found = self.servers.iter().enumerate()
+                .flat_map(|(s, server)| server.channels.iter().enumerate().map(move |(c, channel)| (s, c, channel)))
+                .find(|&(_, _, channel)| {
+                    Some(channel.id) != active && if pinged { channel.mentions > 0 } else { channel.is_unread() }
+                })
+                .map(|(s, c, _)| (s, c));
+            if found.is_some() {
+                break;
+            }
+        }
+        match found {
+            Some((server, channel)) => {
+                self.active_server = server;
+                self.servers[server].active_channel = channel;
+                self.open_channel();
+            },
+            None => self.notify(String::from("No unread channels")),
+        }
+    }
+    /// Clear the active channel's badges and queue up telling Discord we've seen
+    /// up to its newest message. Passing channels by with j/k doesn't count.
+    fn mark_read(&mut self) {
+        if self.mode == Mode::ChannelSelect || self.mode == Mode::ServerSelect {
+            return;
+        }
+        let mut read = None;
+        {
+            let server = &mut self.servers[self.active_server];
+            let index = server.active_channel;
+            if let Some(channel) = server.channels.get_mut(index) {
+                channel.unread = 0;
+                channel.mentions = 0;
+                if let Some(last) = channel.last_message_id {
+                    if channel.last_read.map_or(true, |read| read < last) {
+                        channel.last_read = Some(last);
+                        read = Some((channel.id, last));
+                    }
+                }
+            }
+        }
+        if let Some((channel_id, last)) = read {
+            self.pending_acks.insert(channel_id, last);
+            if self.acks_due.is_none() {
+                self.acks_due = Some(time::Instant::now() + time::Duration::from_secs(ACK_DELAY_SECS));
+            }
+        }
+    }
+    fn send_acks(&mut self) {
+        for (channel_id, message_id) in self.pending_acks.drain() {
+            self.to_provider.send(MsgToDiscord::Ack(channel_id, message_id));
+        }
+        self.acks_due = None;
+    }
+    /// Runs between provider messages, for whatever is waiting on the clock
+    fn tick(&mut self) {
+        if self.acks_due.map_or(false, |due| time::Instant::now() >= due) {
+            self.send_acks();
+        }
+    }
+    fn set_read_state(&mut self, read_state: Vec<discord::model::ReadState>) {
+        self.read_state = read_state.into_iter().map(|state| (state.id, state)).collect();
+        for server in self.servers.iter_mut() {
+            for channel in server.channels.iter_mut() {
+                channel.apply_read_state(&self.read_state);
+            }
+        }
+        self.fetch_mentioned();
+        self.mark_read();
+    }
+    /// Load history for channels Discord says we're mentioned in, so the inbox has them.
+    /// Discord only counts mentions, it doesn't say where they are, so this goes back
+    /// BACKFILL_LIMIT messages and anything older than that is missed.
+    fn fetch_mentioned(&mut self) {
+        let provider = self.to_provider.clone();
+        for channel in self.servers.iter_mut().flat_map(|server| server.channels.iter_mut()) {
+            if channel.mentions > 0 && !channel.history_requested {
+                channel.history_requested = true;
+                provider.send(MsgToDiscord::GetMessages(channel.id, GetMessages::MostRecent, BACKFILL_LIMIT));
+            }
+        }
+    }
+    /// Put whatever in these messages pings us into the inbox
+    fn collect_mentions(&mut self, channel_id: discord::model::ChannelId, messages: &[Message]) {
+        let (place, mentions) = {
+            let (server, channel) = match self.locate(channel_id) {
+                Some(found) => found,
+                None => return,
+            };
+            let pings: Vec<&Message> = messages.iter()
+                .filter(|msg| Some(msg.author.id) != self.me && self.mentions_me(server, msg))
+                .collect();
+            if pings.is_empty() {
+                return;
+            }
+            // These may not be in the channel yet, so neither are the people they mention
+            let mut names = self.names_for(server, Some(channel));
+            for user in pings.iter().flat_map(|msg| msg.mentions.iter()) {
+                names.users.entry(user.id).or_insert_with(|| user.name.clone());
+            }
+            let mentions: Vec<(Message, String, String)> = pings.into_iter()
+                .map(|msg| {
+                    let author = String::from(names.display_name(&msg.author));
+                    let text = markdown::plain(&msg.content, &|token| names.resolve(token));
+                    (msg.clone(), author, text)
+                })
+                .collect();
+            (place(server, channel), mentions)
+        };
+        for (msg, author, text) in mentions {
+            self.inbox.add(msg, place.clone(), author, text);
+        }
+    }
+    /// Notify about a new message if it's somewhere that's allowed to, and we're not in do-not-disturb
+    fn alert(&mut self, msg: &Message) {
+        if self.dnd || Some(msg.author.id) == self.me {
+            return;
+        }
+        let (place, content) = {
+            let (server, channel) = match self.locate(msg.channel_id) {
+                Some(found) => found,
+                None => return,
+            };
+            let wanted = match self.config.notify_level(&server.server_info.name, channel.bare_name()) {
+                notify::Level::All => true,
+                notify::Level::Mentions => self.pings_me(server, msg),
+                notify::Level::Nothing => false,
+            };
+            if !wanted {
+                return;
+            }
+            let mut names = self.names_for(server, Some(channel));
+            for user in msg.mentions.iter() {
+                names.users.entry(user.id).or_insert_with(|| user.name.clone());
+            }
+            (place(server, channel), markdown::plain(&msg.content, &|token| names.resolve(token)))
+        };
+        let alert = notify::Alert {
+            author: &msg.author.name,
+            place: &place,
+            content: &content,
+        };
+        self.urgent = true;
+        if let Err(error) = notify::ring(&alert) {
+            self.notify_error(format!("Couldn't ring the bell: {}", error));
+        }
+        let command = self.config.notify_command.clone();
+        if let Some(command) = command {
+            if let Err(error) = notify::run_command(&command, &alert) {
+                self.notify_error(format!("Couldn't run notify_command: {}", error));
+            }
+        }
+    }
+    /// The server and channel a channel id belongs to
+    fn locate(&self, channel_id: discord::model::ChannelId) -> Option<(&Server, &Channel)> {
+        self.servers.iter()
+            .filter_map(|server| server.channels.iter().find(|channel| channel.id == channel_id).map(|channel| (server, channel)))
+            .next()
+    }
+    /// Open the channel of the highlighted mention and select it, fetching the
+    /// messages around it first if they aren't loaded
+    fn jump_to_mention(&mut self) {
+        let target = self.inbox.mentions.get(self.inbox.selected).map(|mention| (mention.message.channel_id, mention.message.id));
+        self.prev_mode();
+        let (channel_id, id) = match target {
+            Some(target) => target,
+            None => return,
+        };
+        let found = self.servers.iter().enumerate()
+            .filter_map(|(s, server)| server.channels.iter().position(|channel| channel.id == channel_id).map(|c| (s, c)))
+            .next();
+        let (server, index) = match found {
+            Some(found) => found,
+            None => return self.notify(String::from("That channel isn't around any more")),
+        };
+        {
+            let channel = &mut self.servers[server].channels[index];
+            if !channel.messages.iter().any(|msg| msg.id == id) {
+                // Start over from the mention, newer messages come back when scrolled down to
+                channel.messages.clear();
+                channel.history_requested = true;
+                channel.loading_older = false;
+                channel.reached_start = false;
+                channel.gap_after = Some(id);
+                channel.backfilling = false;
+                self.to_provider.send(MsgToDiscord::GetMessages(channel_id, GetMessages::Around(id), HISTORY_PAGE));
+            }
+        }
+        self.active_server = server;
+        self.servers[server].active_channel = index;
+        self.open_channel();
+        self.select_message(id);
+    }
+    /// Highlight a message in the active channel, or wait for it to arrive
+    fn select_message(&mut self, id: discord::model::MessageId) {
+        let loaded = self.active_channel_ref().map_or(false, |channel| channel.messages.iter().any(|msg| msg.id == id));
+        if !loaded {
+            self.pending_jump = Some(id);
+            return;
+        }
+        self.pending_jump = None;
+        self.selected = Some(id);
+        if self.mode != Mode::MessageSelect {
+            self.switch_mode(Mode::MessageSelect);
+        }
+        self.move_selection(0);
+    }
+    /// Someone read a channel on another client
+    fn set_read(&mut self, channel_id: discord::model::ChannelId, message_id: Option<discord::model::MessageId>) {
+        if let Some(channel) = self.channel_mut(channel_id) {
+            channel.last_read = message_id;
+            if message_id >= channel.last_message_id {
+                channel.unread = 0;
+                channel.mentions = 0;
+            }
+        }
+    }
+    /// DMs always ping, in servers it takes a mention of us, one of our roles, or everyone
+    fn pings_me(&self, server: &Server, msg: &Message) -> bool {
+        server.is_private() || self.mentions_me(server, msg)
+    }
+    /// Mentions us, one of our roles, or everyone, wherever it was said
+    fn mentions_me(&self, server: &Server, msg: &Message) -> bool {
+        let mut names = Names::default();
+        names.me = self.me;
+        names.my_roles = self.my_roles(server.server_info.id);
+        names.mentions_me(msg)
+    }
+    fn my_roles(&self, server_id: discord::model::ServerId) -> Vec<discord::model::RoleId> {
+        self.live_servers.get(&server_id)
+            .and_then(|live| live.members.iter().find(|member| Some(member.user.id) == self.me))
+            .map_or(vec![], |member| member.roles.clone())
+    }
+
+    /// Scroll towards older messages, fetching another page once the top is reached
+    fn scroll_up(&mut self, amount: usize) {
+        self.scroll_pos += amount;
+        let provider = self.to_provider.clone();
+        let (width, height) = self.chat_size.get();
+        let names = self.names();
+        let server = &mut self.servers[self.active_server];
+        if server.channels.len() == 0 {
+            return;
+        }
+        let channel = server.active_channel();
+        let layout = chatwidget::Layout { width, names: &names, divider: channel.divider, clock: self.config.clock };
+        let top = layout.oldest_page(&channel.messages, height);
+        if self.scroll_pos >= top {
+            self.scroll_pos = top;
+            channel.request_older(&provider);
+        }
+    }
+    fn scroll_down(&mut self, amount: usize) {
+        self.scroll_pos = self.scroll_pos.checked_sub(amount).unwrap_or(0);
+        if self.scroll_pos == 0 {
+            // After jumping back to an old mention, catch up with what came after it
+            let provider = self.to_provider.clone();
+            {
+                let server = &mut self.servers[self.active_server];
+                let index = server.active_channel;
+                if let Some(channel) = server.channels.get_mut(index) {
+                    channel.request_gap(&provider);
+                }
+            }
+            self.mark_read();
+        }
+    }
+
+    fn set_connection_state(&mut self, state: ConnectionState) {
+        let reconnected = state == ConnectionState::Connected && self.connection != ConnectionState::Connected;
+        self.connection = state;
+        if reconnected {
+            let provider = self.to_provider.clone();
+            for channel in self.servers.iter_mut().flat_map(|server| server.channels.iter_mut()) {
+                channel.request_missed(&provider);
+            }
+        }
     }
 
     fn active_server(&mut self) -> &mut Server {
@@ -173,37 +784,98 @@ impl AppState {
         self.to_provider.send(MsgToDiscord::GetServers);
     }
     fn quit(&mut self) {
+        self.send_acks();
         self.mode = Mode::Exiting;
         self.to_provider.send(MsgToDiscord::Logout);
     }
+    /// Servers we already have (on a reload) keep their channels, and we stay
+    /// in the one we were in if it's still there
     fn set_servers(&mut self, servers: Vec<discord::model::ServerInfo>) {
-        self.servers.clear();
-
-        let mut mut_servers = servers.to_vec();
-        mut_servers.reverse();
-        for server_info in mut_servers.iter() {
-            self.servers.push(Server{
-                channels: Vec::new(),
-                active_channel: 0,
-                server_info: server_info.clone(),
-            });
-            self.to_provider.send(MsgToDiscord::GetChannels(server_info.id));
+        let active_id = self.servers.get(self.active_server).map(|server| server.server_info.id);
+        let mut known: Vec<Server> = self.servers.drain(..).collect();
+        // Direct Messages isn't one of the servers we get back, hang on to it
+        if let Some(index) = known.iter().position(|server| server.is_private()) {
+            self.servers.push(known.remove(index));
+        }
+
+        for server_info in servers.into_iter().rev() {
+            let server = match known.iter().position(|server| server.server_info.id == server_info.id) {
+                Some(index) => {
+                    let mut server = known.remove(index);
+                    server.server_info = server_info;
+                    server
+                },
+                None => Server::new(server_info),
+            };
+            self.to_provider.send(MsgToDiscord::GetChannels(server.server_info.id));
+            self.servers.push(server);
         };
+        // Always there, so an account in no servers still has somewhere to be
+        if !self.servers.iter().any(|server| server.is_private()) {
+            self.servers.insert(0, Server::direct_messages());
+        }
+        self.active_server = self.servers.iter()
+            .position(|server| Some(server.server_info.id) == active_id)
+            .or_else(|| self.servers.iter().position(|server| !server.is_private()))
+            .unwrap_or(0);
     }
-    fn set_channels(&mut self, owner: discord::model::ServerId, channels: Vec<discord::model::PublicChannel>) {
-        let temp = self.servers.clone();
-        let (i, owning_server) = temp.iter().enumerate().find(|&(i, server)| {
-            server.server_info.id == owner
-        }).unwrap();
-
-        self.servers[i].channels = channels.iter().map(|d_channel| {
-            let d_channel = d_channel.clone();
-            Channel {
-                name: format!("{} ({})", d_channel.name, d_channel.kind.name()),
-                id: d_channel.id,
-                messages: vec!(),
+    fn set_private_channels(&mut self, channels: Vec<discord::model::Channel>) {
+        if !self.servers.iter().any(|server| server.is_private()) {
+            self.servers.insert(0, Server::direct_messages());
+            if self.servers.len() > 1 {
+                self.active_server += 1;
             }
+        }
+        let index = self.servers.iter().position(|server| server.is_private()).unwrap();
+        {
+            let direct_messages = &mut self.servers[index];
+            for mut dm in channels.iter().filter_map(Channel::from_private) {
+                if !direct_messages.channels.iter().any(|channel| channel.id == dm.id) {
+                    dm.apply_read_state(&self.read_state);
+                    direct_messages.channels.push(dm);
+                }
+            }
+            direct_messages.sort_by_activity();
+        }
+        self.fetch_mentioned();
+        if index == self.active_server {
+            self.open_channel();
+        }
+    }
+    fn set_channels(&mut self, owner: discord::model::ServerId, channels: Vec<discord::model::PublicChannel>) {
+        let i = match self.servers.iter().position(|server| server.server_info.id == owner) {
+            Some(i) => i,
+            None => return, // gone by the time its channels came back
+        };
+        let active_id = self.servers[i].channels.get(self.servers[i].active_channel).map(|channel| channel.id);
+        let mut known: Vec<Channel> = self.servers[i].channels.drain(..).collect();
+
+        // Channels we already have only take Discord's side of things, what's
+        // loaded, drafted, unread or read here stays as it was
+        let new_channels: Vec<Channel> = channels.iter().map(|d_channel| {
+            let name = format!("{} ({})", d_channel.name, d_channel.kind.name());
+            let mut channel = match known.iter().position(|channel| channel.id == d_channel.id) {
+                Some(index) => known.remove(index),
+                None => {
+                    let mut channel = Channel::new(d_channel.id, name.clone());
+                    channel.apply_read_state(&self.read_state);
+                    channel
+                },
+            };
+            channel.name = name;
+            channel.last_message_id = max(channel.last_message_id, d_channel.last_message_id);
+            channel.topic = d_channel.topic.clone();
+            channel
         }).collect();
+        self.servers[i].active_channel = new_channels.iter()
+            .position(|channel| Some(channel.id) == active_id)
+            .unwrap_or(0);
+        self.servers[i].channels = new_channels;
+        self.fetch_mentioned();
+
+        if i == self.active_server {
+            self.open_channel();
+        }
     }
 
 
@@ -226,77 +898,655 @@ impl AppState {
     }
     fn perform_command(&mut self, command: String){
         assert_eq!(self.mode, Mode::Command);
-        match command.as_ref() {
-            "q" => self.quit(),
-            _ => self.print(format!("Unknown command {}", command))
+        // Back out first, so commands that switch modes or quit have the last word
+        self.prev_mode();
+        if let Err(error) = commands::run(self, &command) {
+            self.notify_error(error);
+        }
+    }
+
+    fn active_channel_ref(&self) -> Option<&Channel> {
+        let server = &self.servers[self.active_server];
+        server.channels.get(server.active_channel)
+    }
+    /// Newest message in the active channel that we're allowed to edit or delete
+    fn latest_own_message(&self) -> Option<discord::model::MessageId> {
+        let me = match self.me {
+            Some(me) => me,
+            None => return None,
+        };
+        self.active_channel_ref()
+            .and_then(|channel| channel.messages.iter().rev().find(|msg| msg.author.id == me))
+            .map(|msg| msg.id)
+    }
+    /// Load a message into the input line, Enter saves the edit
+    fn begin_edit(&mut self, id: discord::model::MessageId) {
+        let found = self.active_channel_ref()
+            .and_then(|channel| channel.messages.iter().find(|msg| msg.id == id))
+            .map(|msg| (msg.channel_id, msg.content.clone()));
+        if let Some((channel_id, content)) = found {
+            self.park_draft();
+            self.selected = Some(id);
+            self.editing = Some((channel_id, id));
+            self.input.set(content);
+            self.switch_mode(Mode::TextInput);
+        }
+    }
+    fn begin_delete(&mut self, id: discord::model::MessageId) {
+        self.selected = Some(id);
+        self.switch_mode(Mode::ConfirmDelete);
+    }
+    fn delete_selected(&mut self) {
+        let channel_id = self.active_channel_id();
+        if let (Some(channel_id), Some(id)) = (channel_id, self.selected.take()) {
+            self.to_provider.send(MsgToDiscord::DeleteMessage(channel_id, id));
+        }
+    }
+    fn cancel_edit(&mut self) {
+        self.reacting = None;
+        self.editing = None;
+        self.browsing = None;
+        self.restore_draft();
+        self.release_selection();
+    }
+    /// Put the draft away in its channel so the input can be used for something else
+    fn park_draft(&mut self) {
+        self.browsing = None;
+        let has_home = match self.draft_channel {
+            Some(id) => self.channel_mut(id).is_some(),
+            None => false,
+        };
+        // With nowhere to put it, it stays where it is
+        if self.draft_parked || !has_home {
+            return;
+        }
+        let draft = self.input.take_draft();
+        if let Some(channel) = self.draft_channel.and_then(|id| self.channel_mut(id)) {
+            channel.draft = draft;
+        }
+        self.draft_parked = true;
+    }
+    /// Bring the draft back, dropping whatever the input was being used for
+    fn restore_draft(&mut self) {
+        if !self.draft_parked {
+            return;
+        }
+        let draft = self.draft_channel
+            .and_then(|id| self.channel_mut(id))
+            .map(|channel| std::mem::replace(&mut channel.draft, Draft::default()));
+        if let Some(draft) = draft {
+            self.input.set_draft(draft);
+        }
+        self.draft_parked = false;
+    }
+    /// Up past the first line of the input: bring back an older sent message, like a shell
+    fn recall_older(&mut self) {
+        let channel_id = match self.active_channel_id() {
+            Some(id) => id,
+            None => return,
+        };
+        let index = self.browsing.as_ref().map_or(0, |&(i, _)| i + 1);
+        let text = {
+            let entries = self.history.entries(channel_id);
+            if index >= entries.len() {
+                return;
+            }
+            entries[entries.len() - 1 - index].clone()
+        };
+        let stash = match self.browsing.take() {
+            Some((_, stash)) => stash,
+            None => self.input.take_draft(),
+        };
+        self.browsing = Some((index, stash));
+        self.input.set(text);
+    }
+    /// Every channel and DM there is, ranked against the quick-switcher's query
+    fn switch_targets(&self) -> Vec<Target> {
+        let mut targets = vec![];
+        for (server_index, server) in self.servers.iter().enumerate() {
+            for (channel_index, channel) in server.channels.iter().enumerate() {
+                let label = if server.is_private() {
+                    format!("@{}", channel.name)
+                } else {
+                    format!("{} #{}", server.server_info.name, channel.bare_name())
+                };
+                targets.push(Target { label, id: channel.id, server: server_index, channel: channel_index });
+            }
+        }
+        // Where we are now isn't somewhere to switch to, rank it as if it weren't recent
+        let recent = match self.active_channel_id() {
+            Some(active) => self.recent_channels.iter().cloned().filter(|&id| id != active).collect(),
+            None => self.recent_channels.clone(),
+        };
+        fzf::rank(targets, self.switcher.query.text(), &recent)
+    }
+    fn jump_to_selected(&mut self) {
+        let target = self.switch_targets().into_iter().nth(self.switcher.selected);
+        self.prev_mode();
+        if let Some(target) = target {
+            self.active_server = target.server;
+            self.servers[target.server].active_channel = target.channel;
+            self.open_channel();
+        }
+    }
+    /// Who and what the ids in the active channel's mentions stand for
+    fn names(&self) -> Names {
+        self.names_for(&self.servers[self.active_server], self.active_channel_ref())
+    }
+    fn names_for(&self, server: &Server, channel: Option<&Channel>) -> Names {
+        let mut names = Names::default();
+        names.me = self.me;
+        names.my_roles = self.my_roles(server.server_info.id);
+        if let Some(channel) = channel {
+            for msg in channel.messages.iter() {
+                for user in Some(&msg.author).into_iter().chain(msg.mentions.iter()) {
+                    names.users.insert(user.id, user.name.clone());
+                }
+            }
+        }
+        // Nicknames win over usernames
+        if let Some(live) = self.live_servers.get(&server.server_info.id) {
+            for member in live.members.iter() {
+                let name = member.nick.as_ref().unwrap_or(&member.user.name);
+                names.users.insert(member.user.id, name.clone());
+                // The highest role with a color decides theirs
+                let color = live.roles.iter()
+                    .filter(|role| member.roles.contains(&role.id) && role.color != 0)
+                    .max_by_key(|role| role.position)
+                    .and_then(|role| mentions::role_color(role.color));
+                if let Some(color) = color {
+                    names.colors.insert(member.user.id, color);
+                }
+            }
+            for role in live.roles.iter() {
+                names.roles.insert(role.id, role.name.clone());
+            }
+        }
+        for channel in self.servers.iter().flat_map(|server| server.channels.iter()) {
+            names.channels.insert(channel.id, String::from(channel.bare_name()));
+        }
+        names
+    }
+    /// Everything @, # and : can complete to here, the likeliest first
+    fn completion_entries(&self) -> Vec<Entry> {
+        let mut entries = vec![];
+        let server = &self.servers[self.active_server];
+        // People who spoke recently, then everyone else in the server
+        if let Some(channel) = self.active_channel_ref() {
+            for msg in channel.messages.iter().rev() {
+                entries.push(Entry::user(&msg.author.name, msg.author.id));
+            }
+        }
+        let live = self.live_servers.get(&server.server_info.id);
+        if let Some(live) = live {
+            for member in live.members.iter() {
+                if let Some(ref nick) = member.nick {
+                    entries.push(Entry::user(nick, member.user.id)
+                        .labelled(format!("@{} ({})", nick, member.user.name)));
+                }
+                entries.push(Entry::user(&member.user.name, member.user.id));
+            }
+        }
+        entries.push(Entry::literal("@here"));
+        entries.push(Entry::literal("@everyone"));
+        if !server.is_private() {
+            for channel in server.channels.iter() {
+                entries.push(Entry::channel(channel.bare_name(), channel.id));
+            }
+        }
+        if let Some(live) = live {
+            for emoji in live.emojis.iter() {
+                entries.push(Entry::custom_emoji(&emoji.name, emoji.id));
+            }
+        }
+        entries.extend(complete::emoji_entries());
+        entries
+    }
+    /// Tab: complete the word before the cursor, or move on to the next candidate
+    fn complete(&mut self, step: isize) {
+        if let Some(ref mut completion) = self.completion {
+            completion.step(step);
+            insert_entry(&mut self.input, completion.start, completion.current());
+            return;
+        }
+        let found = if self.mode_stack.last() == Some(&Mode::Command) {
+            commands::completions(self, self.input.before_cursor())
+        } else {
+            complete::token(self.input.before_cursor())
+                .map(|(start, token)| (start, String::from(token), self.completion_entries()))
+        };
+        let (start, token, entries) = match found {
+            Some(found) => found,
+            None => return,
+        };
+        let candidates = complete::candidates(&entries, &token);
+        if candidates.is_empty() {
+            return self.notify(format!("Nothing matches {}", token));
+        }
+        let mut completion = Completion { start, original: token, candidates, index: 0 };
+        if step < 0 {
+            completion.step(step);
+        }
+        insert_entry(&mut self.input, start, completion.current());
+        // Only worth a list if there's a choice to make
+        if completion.candidates.len() > 1 {
+            self.completion = Some(completion);
+        }
+    }
+    fn cancel_completion(&mut self) {
+        if let Some(completion) = self.completion.take() {
+            self.input.replace_back_to(completion.start, &completion.original);
+        }
+    }
+    /// Down past the last line: a newer sent message, and finally the draft again
+    fn recall_newer(&mut self) {
+        let channel_id = match self.active_channel_id() {
+            Some(id) => id,
+            None => return,
+        };
+        match self.browsing.take() {
+            None => (),
+            Some((0, stash)) => self.input.set_draft(stash),
+            Some((index, stash)) => {
+                let text = {
+                    let entries = self.history.entries(channel_id);
+                    entries[entries.len() - index].clone()
+                };
+                self.browsing = Some((index - 1, stash));
+                self.input.set(text);
+            },
+        }
+    }
+    /// The highlight only outlives an action if we're going back to MessageSelect
+    fn release_selection(&mut self) {
+        if self.mode != Mode::MessageSelect {
+            self.selected = None;
+            self.show_details = false;
+        }
+    }
+
+    /// The selected message in the active channel
+    fn selected_message(&self) -> Option<&Message> {
+        let id = match self.selected {
+            Some(id) => id,
+            None => return None,
+        };
+        self.active_channel_ref()
+            .and_then(|channel| channel.messages.iter().find(|msg| msg.id == id))
+    }
+    fn selected_is_mine(&self) -> bool {
+        match (self.selected_message(), self.me) {
+            (Some(msg), Some(me)) => msg.author.id == me,
+            _ => false,
+        }
+    }
+    /// Move the highlight `delta` messages towards the newest one, scrolling to keep it in view
+    fn move_selection(&mut self, delta: isize) {
+        let (width, height) = self.chat_size.get();
+        let names = self.names();
+        let provider = self.to_provider.clone();
+        let selected = self.selected;
+        let server = &mut self.servers[self.active_server];
+        if server.channels.len() == 0 {
+            return;
+        }
+        let channel = server.active_channel();
+        let n = channel.messages.len();
+        if n == 0 {
+            return;
+        }
+        self.scroll_pos = min(self.scroll_pos, n - 1);
+
+        let current = selected.and_then(|id| channel.messages.iter().position(|msg| msg.id == id));
+        let index = match current {
+            Some(i) => min(max(i as isize + delta, 0) as usize, n - 1),
+            // Start from the newest message on screen
+            None => n - 1 - self.scroll_pos,
+        };
+        if index == 0 && delta < 0 {
+            channel.request_older(&provider);
+        }
+        self.selected = Some(channel.messages[index].id);
+
+        let newest_visible = (n - 1).saturating_sub(self.scroll_pos);
+        if index > newest_visible {
+            self.scroll_pos = (n - 1).saturating_sub(index);
+        }
+        // Scroll back until everything from the selection down fits on screen,
+        // each step drops the newest message still counted
+        let layout = chatwidget::Layout { width, names: &names, divider: channel.divider, clock: self.config.clock };
+        let heights = layout.heights(&channel.messages, index, n - self.scroll_pos);
+        let mut total: usize = heights.iter().sum();
+        let mut counted = heights.len();
+        while self.scroll_pos < (n - 1).saturating_sub(index) && total > height {
+            counted -= 1;
+            total -= heights[counted];
+            self.scroll_pos += 1;
+        }
+    }
+    fn reply_selected(&mut self) {
+        let reply = self.selected_message().map(|msg| {
+            let quoted = msg.content.lines().next().unwrap_or("");
+            format!("> {}\n<@{}> ", quoted, msg.author.id.0)
+        });
+        if let Some(reply) = reply {
+            // Replying is done with the message, go back to Normal and type
+            self.prev_mode();
+            self.release_selection();
+            // Quote on top of whatever was already being written
+            self.input.prepend(&reply);
+            self.switch_mode(Mode::TextInput);
+        }
+    }
+    /// Copy through the terminal (OSC 52), which also works over ssh
+    fn copy_selected(&mut self) {
+        let content = match self.selected_message() {
+            Some(msg) => msg.content.clone(),
+            None => return,
+        };
+        let mut stdout = io::stdout();
+        let copied = write!(stdout, "\x1b]52;c;{}\x07", base64::encode(content.as_bytes()))
+            .and_then(|_| stdout.flush());
+        match copied {
+            Ok(()) => self.notify(String::from("Copied message to clipboard")),
+            Err(error) => self.notify_error(format!("Couldn't copy message: {}", error)),
+        }
+    }
+    fn begin_react(&mut self) {
+        let target = self.selected_message().map(|msg| (msg.channel_id, msg.id));
+        if let Some(target) = target {
+            self.park_draft();
+            self.reacting = Some(target);
+            self.switch_mode(Mode::TextInput);
+        }
+    }
+    fn open_links_selected(&mut self) {
+        let links = match self.selected_message() {
+            Some(msg) => links(msg),
+            None => return,
         };
-        self.prev_mode()
+        if links.is_empty() {
+            self.notify(String::from("No links in that message"));
+            return;
+        }
+        let opener = std::env::var("BROWSER").unwrap_or_else(|_| String::from(
+            if cfg!(target_os = "macos") { "open" } else { "xdg-open" }
+        ));
+        for link in links.iter() {
+            let spawned = Command::new(&opener)
+                .arg(link)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn();
+            if let Err(error) = spawned {
+                self.notify_error(format!("Couldn't run {}: {}", opener, error));
+                return;
+            }
+        }
+        self.notify(format!("Opened {} link(s)", links.len()));
+    }
+    /// Hands the draft over to $VISUAL/$EDITOR through a temp file, and takes back whatever was saved
+    fn compose_in_editor(&mut self) {
+        let editor = std::env::var("VISUAL")
+            .or_else(|_| std::env::var("EDITOR"))
+            .unwrap_or_else(|_| String::from("vi"));
+        let (path, mut file) = match draft_file() {
+            Ok(created) => created,
+            Err(error) => return self.notify_error(format!("Couldn't create a file for the draft: {}", error)),
+        };
+        let written = file.write_all(self.input.text().as_bytes());
+        drop(file);
+        if let Err(error) = written {
+            std::fs::remove_file(&path).ok();
+            return self.notify_error(format!("Couldn't write draft to {}: {}", path.display(), error));
+        }
+
+        // Put the terminal back to normal for the editor, then raw again for us
+        let saved = stty(&["-g"]);
+        stty(&["sane"]);
+        print!("{}{}", termion::clear::All, termion::cursor::Show);
+        io::stdout().flush().ok();
+        // Through the shell, so EDITOR="code --wait" works
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$1\"", editor))
+            .arg("sh")
+            .arg(&path)
+            .status();
+        match saved {
+            Some(saved) => { stty(&[saved.trim()]); },
+            None => { stty(&["raw", "-echo"]); },
+        }
+        self.needs_clear = true;
+
+        let mut text = String::new();
+        let read = File::open(&path).and_then(|mut f| f.read_to_string(&mut text));
+        std::fs::remove_file(&path).ok();
+        match status {
+            Ok(ref status) if status.success() => (),
+            Ok(status) => return self.notify_error(format!("{} exited with {}, draft left as it was", editor, status)),
+            Err(error) => return self.notify_error(format!("Couldn't run {}: {}", editor, error)),
+        }
+        if let Err(error) = read {
+            return self.notify_error(format!("Couldn't read the draft back: {}", error));
+        }
+        // Editors like to end the file with a newline, Discord doesn't need it
+        while text.ends_with('\n') {
+            text.pop();
+        }
+        self.input.set(text);
+        if self.mode != Mode::TextInput {
+            self.switch_mode(Mode::TextInput);
+        }
     }
 
     fn process_text_input(&mut self) {
-        let text = self.content.clone();
-        self.content = String::from("");
-        self.offset = 0;
+        let encoded = complete::encode(self.input.text(), self.input.completed(), &self.completion_entries());
+        let draft = self.input.take_draft();
+        let text = String::from(draft.text());
+        self.browsing = None;
+        // The draft comes back unless this was the draft being sent
+        self.restore_draft();
+        if self.mode == Mode::Command {
+            return self.perform_command(text);
+        }
+        if let Some((channel_id, id)) = self.reacting.take() {
+            // A completed custom emoji is <:name:id>, reactions want name:id
+            let emoji = String::from(encoded.trim());
+            let emoji = if emoji.starts_with("<:") && emoji.ends_with('>') {
+                String::from(&emoji[2..emoji.len() - 1])
+            } else {
+                emoji
+            };
+            if !emoji.is_empty() {
+                self.to_provider.send(MsgToDiscord::AddReaction(channel_id, id, emoji));
+            }
+            return self.release_selection();
+        }
+        if let Some((channel_id, id)) = self.editing.take() {
+            if text.is_empty() {
+                // Discord won't take an empty message, offer to delete it instead
+                return self.begin_delete(id);
+            }
+            self.to_provider.send(MsgToDiscord::EditMessage(channel_id, id, encoded));
+            return self.release_selection();
+        }
         match self.mode {
-            Mode::Normal => self.send_message(text),
-            Mode::Command => self.perform_command(text),
+            Mode::Normal => self.send_message(draft, encoded),
             _ => panic!("How did we get to {:?} from TextInput? Stack: {:?}", self.mode, self.mode_stack)
 
         }
     }
     fn handle_key(&mut self, key: Key) {
+        // Any key means someone's looking, so the title can calm down
+        if self.urgent {
+            self.urgent = false;
+            notify::reset_title().ok();
+        }
         match self.mode {
             Mode::Normal => {
                 match key {
                     Key::Char('i') => self.switch_mode(Mode::TextInput),
-                    Key::Char(':') => self.switch_mode(Mode::Command),
+                    Key::Char(':') => {
+                        self.park_draft();
+                        self.switch_mode(Mode::Command);
+                    },
                     Key::Char('s') => self.switch_mode(Mode::ServerSelect),
                     Key::Char('c') => self.switch_mode(Mode::ChannelSelect),
-                    //Key::Ctrl('k') => self.switch_mode(Mode::Fzf),
-                    //Key::Char('/') => self.switch_mode(Mode::Fzf),
+                    Key::Ctrl('k') | Key::Char('/') => {
+                        self.switcher = Switcher::new();
+                        self.switch_mode(Mode::Fzf);
+                    },
                     //Key::Char('k') => self.mode = Mode::Command,
                     //Key::Char('j') => self.mode = Mode::Command,
-                    Key::Ctrl('u') => self.scroll_pos += 5,
-                    Key::Ctrl('d') => self.scroll_pos = self.scroll_pos.checked_sub(5).unwrap_or(0),
+                    Key::Ctrl('u') => self.scroll_up(5),
+                    Key::Ctrl('d') => self.scroll_down(5),
+                    Key::Alt('a') => self.jump_to_unread(),
+                    Key::Char('@') => {
+                        self.inbox.selected = 0;
+                        self.switch_mode(Mode::Mentions);
+                    },
+                    Key::Esc => self.notices.clear(),
+                    Key::Char('e') => match self.latest_own_message() {
+                        Some(id) => self.begin_edit(id),
+                        None => self.notify(String::from("No message of yours to edit here")),
+                    },
+                    Key::Char('d') => match self.latest_own_message() {
+                        Some(id) => self.begin_delete(id),
+                        None => self.notify(String::from("No message of yours to delete here")),
+                    },
+                    Key::Char('v') => {
+                        self.switch_mode(Mode::MessageSelect);
+                        self.move_selection(0);
+                    },
+                    Key::Ctrl('x') => self.compose_in_editor(),
+                    _ => ()
+                }
+            },
+            Mode::MessageSelect => {
+                match key {
+                    Key::Char('j') | Key::Down => self.move_selection(1),
+                    Key::Char('k') | Key::Up => self.move_selection(-1),
+                    Key::Char('r') => self.reply_selected(),
+                    Key::Char('y') => self.copy_selected(),
+                    Key::Char('+') => self.begin_react(),
+                    Key::Char('o') => self.open_links_selected(),
+                    Key::Char('\n') => self.show_details = !self.show_details,
+                    Key::Char('e') => match self.selected {
+                        Some(id) if self.selected_is_mine() => self.begin_edit(id),
+                        _ => self.notify(String::from("You can only edit your own messages")),
+                    },
+                    Key::Char('d') => match self.selected {
+                        Some(id) if self.selected_is_mine() => self.begin_delete(id),
+                        _ => self.notify(String::from("You can only delete your own messages")),
+                    },
+                    Key::Esc => {
+                        self.prev_mode();
+                        self.release_selection();
+                    },
+                    _ => ()
+                }
+            },
+            Mode::ConfirmDelete => {
+                match key {
+                    Key::Char('y') => {
+                        self.delete_selected();
+                        self.prev_mode();
+                        self.release_selection();
+                    },
+                    Key::Char('n') | Key::Esc => {
+                        self.prev_mode();
+                        self.release_selection();
+                    },
                     _ => ()
                 }
             },
             Mode::TextInput => {
+                if self.completion.is_some() {
+                    match key {
+                        Key::Char('\t') => return self.complete(1),
+                        Key::BackTab => return self.complete(-1),
+                        // Enter takes the candidate, it doesn't send yet
+                        Key::Char('\n') => {
+                            self.completion = None;
+                            return;
+                        },
+                        Key::Esc => return self.cancel_completion(),
+                        _ => self.completion = None,
+                    }
+                }
                 match key {
+                    Key::Char('\t') => self.complete(1),
+                    Key::BackTab => self.complete(-1),
                     Key::Char('\n') => {
                         self.prev_mode();
                         self.process_text_input();
                     }
-                    Key::Char(chr) => {
-                        self.add_character(chr);
-                    }
-                    Key::Backspace => {
-                        self.remove_character();
-                    }
                     Key::Esc => {
                         self.prev_mode();
                         if let Mode::Command = self.mode { self.prev_mode(); }
+                        self.cancel_edit();
                     },
-                    _ => ()
+                    Key::Ctrl('x') => self.compose_in_editor(),
+                    key => {
+                        // Up and Down only reach history from the first and last lines
+                        let recall = self.editing.is_none() && self.reacting.is_none()
+                            && self.mode_stack.last() == Some(&Mode::Normal);
+                        if !self.input.handle_key(key) && recall {
+                            match key {
+                                Key::Up => self.recall_older(),
+                                Key::Down => self.recall_newer(),
+                                _ => (),
+                            }
+                        }
+                    }
                 }
             },
             Mode::ChannelSelect => {
                 match key {
-                    Key::Esc => {self.mode = Mode::Normal}
+                    Key::Esc => {self.mode = Mode::Normal; self.mark_read()}
                     Key::Char('\t') => {self.mode = Mode::ServerSelect}
-                    Key::Char('k') => self.active_server().prev_channel(),
-                    Key::Char('j') => self.active_server().next_channel(),
+                    Key::Char('k') => self.prev_channel(),
+                    Key::Char('j') => self.next_channel(),
                     _ => ()
                 }},
             Mode::ServerSelect => {
                 match key {
-                    Key::Esc => {self.mode = Mode::Normal}
+                    Key::Esc => {self.mode = Mode::Normal; self.mark_read()}
                     Key::Char('\t') => {self.mode = Mode::ChannelSelect}
                     Key::Char('k') => self.prev_server(),
                     Key::Char('j') => self.next_server(),
                     _ => ()
                 }
                 },
+            Mode::Fzf => {
+                match key {
+                    Key::Esc => self.prev_mode(),
+                    Key::Char('\n') => self.jump_to_selected(),
+                    Key::Up | Key::Ctrl('p') | Key::BackTab => {
+                        self.switcher.selected = self.switcher.selected.checked_sub(1).unwrap_or(0);
+                    },
+                    Key::Down | Key::Ctrl('n') | Key::Char('\t') => self.switcher.selected += 1,
+                    key => {
+                        if self.switcher.query.handle_key(key) {
+                            self.switcher.selected = 0;
+                        }
+                    },
+                }
+                // Don't run off the end of the list
+                let found = self.switch_targets().len();
+                self.switcher.selected = min(self.switcher.selected, found.checked_sub(1).unwrap_or(0));
+            },
+            Mode::Mentions => {
+                match key {
+                    Key::Esc => self.prev_mode(),
+                    Key::Char('\n') => self.jump_to_mention(),
+                    Key::Char('k') | Key::Up => self.inbox.select(-1),
+                    Key::Char('j') | Key::Down => self.inbox.select(1),
+                    _ => (),
+                }
+            },
             Mode::Command => {
                 match key {
                     Key::Esc => {self.mode = Mode::Normal}
@@ -309,17 +1559,278 @@ impl AppState {
     fn print(&self, what: String){
         self.to_provider.send(MsgToDiscord::Echo(what));
     }
+    fn notify(&mut self, text: String) {
+        self.notices.push(Notice { text, error: false });
+    }
+    fn notify_error(&mut self, text: String) {
+        self.notices.push(Notice { text, error: true });
+    }
+    fn handle_error(&mut self, request: MsgToDiscord, error: ProviderError) {
+        match request {
+            MsgToDiscord::SendMessage(channel_id, text) => {
+                // Put the draft back so it can be fixed up and sent again
+                let in_input = self.draft_channel == Some(channel_id) && !self.draft_parked;
+                if in_input && self.input.is_empty() {
+                    self.input.set(text);
+                    self.notify_error(format!("Message not sent ({}), your draft is back in the input", error));
+                    return;
+                }
+                let restored = match self.channel_mut(channel_id) {
+                    Some(ref mut channel) if !in_input && channel.draft.is_empty() => {
+                        channel.draft = Draft::from(text.clone());
+                        true
+                    },
+                    _ => false,
+                };
+                if restored {
+                    self.notify_error(format!("Message not sent ({}), your draft is back in that channel", error));
+                } else {
+                    self.notify_error(format!("Message not sent ({}): {}", error, text));
+                }
+            },
+            MsgToDiscord::GetMessages(channel_id, GetMessages::After(_), _) => {
+                // Catching up after a reconnect or a jump, scrolling to the bottom
+                // tries a jump's gap again and :reload fetches everything
+                if let Some(channel) = self.channel_mut(channel_id) {
+                    channel.backfilling = false;
+                }
+                self.notify_error(format!("Some newer messages are missing ({}), :reload to fetch them again", error));
+            },
+            MsgToDiscord::GetMessages(channel_id, what, _) => {
+                if let Some(channel) = self.channel_mut(channel_id) {
+                    // Let the next scroll or channel switch try again
+                    match what {
+                        GetMessages::MostRecent => channel.history_requested = false,
+                        _ => channel.loading_older = false,
+                    }
+                }
+                self.notify_error(format!("Couldn't load messages: {}", error));
+            },
+            MsgToDiscord::EditMessage(_, _, text) => {
+                self.notify_error(format!("Couldn't edit message ({}): {}", error, text));
+            },
+            MsgToDiscord::DeleteMessage(..) => self.notify_error(format!("Couldn't delete message: {}", error)),
+            MsgToDiscord::AddReaction(_, _, emoji) => self.notify_error(format!("Couldn't react with {}: {}", emoji, error)),
+            MsgToDiscord::SendDirectMessage(_, text) => self.notify_error(format!("Direct message not sent ({}): {}", error, text)),
+            MsgToDiscord::SetNickname(..) => self.notify_error(format!("Couldn't change nickname: {}", error)),
+            // Probably left the server, they keep their username
+            MsgToDiscord::GetMember(..) => (),
+            // Only the read marker on other clients lags behind, not worth a notice
+            MsgToDiscord::Ack(..) => (),
+            MsgToDiscord::GetServers => self.notify_error(format!("Couldn't load servers: {}", error)),
+            MsgToDiscord::GetChannels(_) => self.notify_error(format!("Couldn't load channels: {}", error)),
+            request => self.notify_error(format!("{:?} failed: {}", request, error)),
+        }
+    }
     fn store_message(&mut self, message: discord::model::Message) {
         let channel_id = message.channel_id;
+        let active = self.active_channel_id() == Some(channel_id);
+        let mine = Some(message.author.id) == self.me;
+        self.collect_mentions(channel_id, &[message.clone()]);
+        self.request_members(channel_id, &[message.clone()]);
+        self.alert(&message);
+        // Keep a scrolled-back viewport on the same messages as new ones arrive
+        if self.scroll_pos > 0 && active {
+            self.scroll_pos += 1;
+        }
+        let pinged = !active && !mine && self.servers.iter()
+            .find(|server| server.channels.iter().any(|channel| channel.id == channel_id))
+            .map_or(false, |server| self.pings_me(server, &message));
         for server in self.servers.iter_mut() {
+            let mut found = false;
             for channel in server.channels.iter_mut() {
                 if channel.id == channel_id {
+                    channel.last_message_id = Some(message.id);
+                    if mine {
+                        // Saying something means we've read what came before it
+                        channel.last_read = Some(message.id);
+                        channel.divider = None;
+                    } else if !active {
+                        channel.unread += 1;
+                        if pinged {
+                            channel.mentions += 1;
+                        }
+                    }
                     channel.messages.push(message);
-                    return;
+                    found = true;
+                    break;
+                }
+            }
+            if found {
+                if server.is_private() {
+                    server.sort_by_activity();
                 }
+                break;
+            }
+        }
+        if active && self.scroll_pos == 0 && !mine {
+            self.mark_read();
+        }
+    }
+    fn store_history(&mut self, channel_id: discord::model::ChannelId, messages: Vec<Message>, reached_start: bool) {
+        // scroll_pos counts from the newest message, so prepending older
+        // messages leaves the viewport where it was
+        self.collect_mentions(channel_id, &messages);
+        self.request_members(channel_id, &messages);
+        let mut still_missing = false;
+        if let Some(channel) = self.channel_mut(channel_id) {
+            let backfilling = channel.backfilling;
+            channel.loading_older = false;
+            channel.reached_start = channel.reached_start || reached_start;
+            channel.fill_gap(&messages, reached_start);
+            channel.merge_messages(messages);
+            channel.last_message_id = max(channel.last_message_id, channel.messages.last().map(|msg| msg.id));
+            // A full page of catching up, and there's more after it
+            still_missing = backfilling && !channel.backfilling && channel.gap_after.is_some();
+        }
+        let active = self.active_channel_id() == Some(channel_id);
+        if still_missing && active && self.scroll_pos == 0 {
+            // Already looking at the bottom, keep going
+            let provider = self.to_provider.clone();
+            if let Some(channel) = self.channel_mut(channel_id) {
+                channel.request_gap(&provider);
+            }
+        } else if still_missing {
+            let place = self.locate(channel_id).map(|(server, channel)| place(server, channel));
+            if let Some(place) = place {
+                self.notify(format!("Not everything said in {} is loaded yet, scroll to the bottom there for the rest", place));
+            }
+        }
+        if active {
+            match self.pending_jump {
+                Some(id) if self.mode == Mode::Normal => self.select_message(id),
+                _ => (),
+            }
+            if self.scroll_pos == 0 {
+                self.mark_read();
+            }
+        }
+    }
+    fn update_message(
+        &mut self,
+        channel_id: discord::model::ChannelId,
+        id: discord::model::MessageId,
+        content: Option<String>,
+        edited_timestamp: Option<DateTime<FixedOffset>>,
+    ) {
+        let in_inbox = self.inbox.mentions.iter().any(|mention| mention.message.id == id);
+        if let (Some(content), true) = (content.as_ref(), in_inbox) {
+            let text = self.locate(channel_id).map(|(server, channel)| {
+                let names = self.names_for(server, Some(channel));
+                markdown::plain(content, &|token| names.resolve(token))
+            });
+            if let Some(text) = text {
+                self.inbox.edit(id, content.clone(), text);
+            }
+        }
+        if let Some(channel) = self.channel_mut(channel_id) {
+            if let Some(msg) = channel.messages.iter_mut().find(|msg| msg.id == id) {
+                if let Some(content) = content {
+                    msg.content = content;
+                }
+                if edited_timestamp.is_some() {
+                    msg.edited_timestamp = edited_timestamp;
+                }
+            }
+        }
+    }
+    fn delete_messages(&mut self, channel_id: discord::model::ChannelId, ids: &[discord::model::MessageId]) {
+        self.inbox.remove(channel_id, ids);
+        let active = self.active_channel_id() == Some(channel_id);
+        let scroll_pos = self.scroll_pos;
+        let mut left = None;
+        if let Some(channel) = self.channel_mut(channel_id) {
+            // Deleted messages below the viewport no longer count towards the scroll
+            let n = channel.messages.len();
+            let below = channel.messages.iter().skip(n.saturating_sub(scroll_pos))
+                .filter(|msg| ids.contains(&msg.id))
+                .count();
+            channel.messages.retain(|msg| !ids.contains(&msg.id));
+            left = Some((channel.messages.len(), below));
+        }
+        if let (true, Some((n, below))) = (active, left) {
+            self.scroll_pos = min(scroll_pos - below, n.saturating_sub(1));
+        }
+    }
+    fn update_member(&mut self, server_id: discord::model::ServerId, user: discord::model::User, roles: Vec<discord::model::RoleId>, nick: Option<String>) {
+        let changed_me = Some(user.id) == self.me;
+        if let Some(live) = self.live_servers.get_mut(&server_id) {
+            match live.members.iter_mut().find(|member| member.user.id == user.id) {
+                Some(member) => {
+                    member.roles = roles;
+                    member.nick = nick.clone();
+                },
+                None => {
+                    // Big servers don't tell us about everyone up front
+                    if self.members_requested.insert((server_id, user.id)) {
+                        self.to_provider.send(MsgToDiscord::GetMember(server_id, user.id));
+                    }
+                    return;
+                },
+            }
+        }
+        if changed_me {
+            match nick {
+                Some(nick) => self.notify(format!("Your nickname is now {}", nick)),
+                None => self.notify(String::from("Your nickname was reset")),
+            }
+        }
+    }
+    /// Someone joined, or we fetched a member we didn't know about
+    fn add_member(&mut self, server_id: discord::model::ServerId, member: discord::model::Member) {
+        if let Some(live) = self.live_servers.get_mut(&server_id) {
+            live.members.retain(|known| known.user.id != member.user.id);
+            live.members.push(member);
+        }
+    }
+    fn remove_member(&mut self, server_id: discord::model::ServerId, user_id: discord::model::UserId) {
+        if let Some(live) = self.live_servers.get_mut(&server_id) {
+            live.members.retain(|member| member.user.id != user_id);
+        }
+    }
+    fn update_role(&mut self, server_id: discord::model::ServerId, role: discord::model::Role) {
+        if let Some(live) = self.live_servers.get_mut(&server_id) {
+            live.roles.retain(|known| known.id != role.id);
+            live.roles.push(role);
+        }
+    }
+    fn delete_role(&mut self, server_id: discord::model::ServerId, role_id: discord::model::RoleId) {
+        if let Some(live) = self.live_servers.get_mut(&server_id) {
+            live.roles.retain(|role| role.id != role_id);
+            for member in live.members.iter_mut() {
+                member.roles.retain(|&role| role != role_id);
+            }
+        }
+    }
+    /// Ask about authors missing from the member list, so they show up with their nickname and color
+    fn request_members(&mut self, channel_id: discord::model::ChannelId, messages: &[Message]) {
+        let server_id = match self.locate(channel_id) {
+            Some((server, _)) if !server.is_private() => server.server_info.id,
+            _ => return,
+        };
+        let missing: Vec<discord::model::UserId> = match self.live_servers.get(&server_id) {
+            Some(live) => messages.iter()
+                .map(|msg| &msg.author)
+                .filter(|author| !mentions::is_bot(author) && !live.members.iter().any(|member| member.user.id == author.id))
+                .map(|author| author.id)
+                .collect(),
+            None => return,
+        };
+        for user_id in missing {
+            if self.members_requested.insert((server_id, user_id)) {
+                self.to_provider.send(MsgToDiscord::GetMember(server_id, user_id));
             }
         }
     }
+    fn channel_mut(&mut self, channel_id: discord::model::ChannelId) -> Option<&mut Channel> {
+        self.servers.iter_mut()
+            .flat_map(|server| server.channels.iter_mut())
+            .find(|channel| channel.id == channel_id)
+    }
+    fn active_channel_id(&self) -> Option<discord::model::ChannelId> {
+        let server = &self.servers[self.active_server];
+        server.channels.get(server.active_channel).map(|channel| channel.id)
+    }
 }
 
 impl Server {
@@ -347,6 +1858,93 @@ impl Server {
     }
 }
 
+/// URLs in a message's text, then its attachments
+fn links(msg: &Message) -> Vec<String> {
+    let mut links: Vec<String> = msg.content
+        .split_whitespace()
+        .map(|word| word.trim_matches(|c| c == '<' || c == '>' || c == '(' || c == ')'))
+        .filter(|word| word.starts_with("http://") || word.starts_with("https://"))
+        .map(String::from)
+        .collect();
+    links.extend(msg.attachments.iter().map(|attachment| attachment.url.clone()));
+    links
+}
+
+/// Puts a completion in the input, remembering the part that's sent differently
+fn insert_entry(input: &mut LineEditor, start: usize, entry: &Entry) {
+    input.replace_back_to(start, &entry.insert);
+    if let Some(len) = entry.encodes() {
+        input.mark(start, start + len, entry.wire.clone());
+    }
+}
+
+/// Where a message was said, for the inbox and notifications
+fn place(server: &Server, channel: &Channel) -> String {
+    if server.is_private() {
+        format!("@{}", channel.name)
+    } else {
+        format!("{} #{}", server.server_info.name, channel.bare_name())
+    }
+}
+
+/// Everything worth knowing about a message that the chat doesn't show
+fn message_details(msg: &Message) -> Vec<String> {
+    let mut lines = vec![
+        format!("Author: {}#{:04}", msg.author.name, msg.author.discriminator),
+        format!("Message ID: {}", msg.id.0),
+        format!("Sent: {}", msg.timestamp),
+    ];
+    if let Some(edited) = msg.edited_timestamp {
+        lines.push(format!("Edited: {}", edited));
+    }
+    if msg.pinned {
+        lines.push(String::from("Pinned"));
+    }
+    for attachment in msg.attachments.iter() {
+        lines.push(format!("Attachment: {} ({} bytes)", attachment.filename, attachment.size));
+        lines.push(format!("  {}", attachment.url));
+    }
+    for reaction in msg.reactions.iter() {
+        let emoji = match reaction.emoji {
+            discord::model::ReactionEmoji::Unicode(ref emoji) => emoji.clone(),
+            discord::model::ReactionEmoji::Custom { ref name, .. } => format!(":{}:", name),
+        };
+        lines.push(format!("Reaction: {} x{}{}", emoji, reaction.count, if reaction.me { " (you)" } else { "" }));
+    }
+    lines
+}
+
+/// A new file only we can read, under a name nobody could have planted a symlink at
+fn draft_file() -> io::Result<(std::path::PathBuf, File)> {
+    let mut attempt = 0;
+    loop {
+        let nanos = time::SystemTime::now().duration_since(time::UNIX_EPOCH).map(|d| d.subsec_nanos()).unwrap_or(0);
+        let name = format!("discord_term-{}-{:08x}-{}.md", std::process::id(), nanos, attempt);
+        let path = std::env::temp_dir().join(name);
+        // create_new won't follow a symlink or reuse a file that's already there
+        let created = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path);
+        match created {
+            Ok(file) => return Ok((path, file)),
+            Err(ref error) if error.kind() == io::ErrorKind::AlreadyExists && attempt < 10 => attempt += 1,
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Runs stty against our terminal, returning what it printed
+fn stty(args: &[&str]) -> Option<String> {
+    Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+}
+
 fn read_token() -> Option<String> {
     let mut data = String::new();
     let mut f = match File::open("./token") {
@@ -361,8 +1959,8 @@ fn read_token() -> Option<String> {
     Some(data)
 }
 
-fn main() {
-    let discord: Discord = match read_token() {
+fn login() -> Discord {
+    match read_token() {
         None => {
             println!("Check readme to see how to save a token for next time.");
             println!("Falling back to email/pw login.");
@@ -381,44 +1979,63 @@ fn main() {
                     panic!("Login Failed: {}", error);
                 }
             }
-    };
-    let backend = RawBackend::new().unwrap();
+    }
+}
+
+fn main() {
+    // --mock runs the whole UI against scripted fake data, no account needed
+    let mock = std::env::args().skip(1).any(|arg| arg == "--mock");
 
     let channel_to_discord = chan::async();
     let channel_from_discord = chan::async();
     // give provider the from_discord sender and the to_discord receiver
-    let provider = DiscordProvider::init(discord, (
+    let provider_channels = (
         channel_from_discord.0.clone(),
         channel_to_discord.1.clone(),
-    ));
-    thread::spawn(|| { provider.start_provider(); });
+    );
+    let provider: Box<ChatBackend> = if mock {
+        Box::new(MockProvider::init(provider_channels))
+    } else {
+        Box::new(DiscordProvider::init(login(), provider_channels))
+    };
+    let backend = RawBackend::new().unwrap();
+    // Alerts write to the window title, put back whatever it was when we're done
+    notify::save_title().ok();
+    thread::spawn(move || { provider.start_provider(); });
 
  
     let mut terminal = Terminal::new(backend).unwrap();
  
-    let mut app_state = AppState::new(channel_to_discord.0.clone(),channel_from_discord.1.clone());
+    let (config, problems) = Config::load();
+    let (history, history_problem) = if mock {
+        // Fake channel ids have no business in the real history file
+        (History::in_memory(), None)
+    } else {
+        History::load()
+    };
+    let mut app_state = AppState::new(channel_to_discord.0.clone(),channel_from_discord.1.clone(), config, history);
+    for problem in problems.into_iter().chain(history_problem) {
+        app_state.notify_error(problem);
+    }
     app_state.get_servers();          
-    let dummy_channel = Channel {
-        name: String::from("Loading..."),
-        id: discord::model::ChannelId {
+    let mut dummy_channel = Channel::new(discord::model::ChannelId {
             0: 1,
         },
-        messages: vec![],
-    };
+        String::from("Loading..."),
+    );
+    dummy_channel.history_requested = true;
+    dummy_channel.reached_start = true;
 
-    let dummy_server = Server {
-        channels: vec![dummy_channel],
-        active_channel: 0,
-        server_info: discord::model::ServerInfo {
-            id: discord::model::ServerId {
-                0: 1234,
-            },
-            name: String::from("Loading..."),
-            icon: None,
-            owner: true,
-            permissions: discord::model::permissions::Permissions::empty(),
+    let mut dummy_server = Server::new(discord::model::ServerInfo {
+        id: discord::model::ServerId {
+            0: 1234,
         },
-    };
+        name: String::from("Loading..."),
+        icon: None,
+        owner: true,
+        permissions: discord::model::permissions::Permissions::empty(),
+    });
+    dummy_server.channels.push(dummy_channel);
 
     app_state.servers.push(dummy_server);
     let terminal = Arc::new(Mutex::new(terminal));
@@ -443,12 +2060,17 @@ fn main() {
 
             let evt = c.unwrap();
             app_state.handle_key(evt);
+            if app_state.needs_clear {
+                app_state.needs_clear = false;
+                terminal.clear().unwrap();
+            }
 
             match app_state.mode {
                 Mode::TextInput => terminal.show_cursor(),
                 Mode::Exiting => {
                     terminal.show_cursor();
                     terminal.clear();
+                    notify::restore_title().ok();
                     std::process::exit(0);
                 }
                 _ => terminal.hide_cursor()
@@ -476,15 +2098,6 @@ fn main() {
                     }
                     terminal.draw();
                 },
-                event::Key::Left => {
-                    app_state.offset = match app_state.offset.checked_sub(1) {
-                        Some(x) => x,
-                        None => 0,
-                    };
-                },
-                event::Key::Right => {
-                    app_state.offset = min(app_state.content.len(), app_state.offset + 1);
-                }
                 event::Key::Ctrl('c') => {
                     tx.send(true);
                     break;
@@ -502,6 +2115,7 @@ fn main() {
     loop {
         chan_select! {
             default => {
+                state.lock().unwrap().tick();
                 thread::sleep_ms(10);
             },
             rx_from_pvdr.recv() -> val => {
@@ -516,15 +2130,66 @@ fn main() {
                         MsgFromDiscord::Channels(server_id, channels) => {
                             app_state.set_channels(server_id, channels)
                         },
+                        MsgFromDiscord::PrivateChannels(channels) => {
+                            app_state.set_private_channels(channels)
+                        },
+                        MsgFromDiscord::CurrentUser(user) => {
+                            app_state.me = Some(user.id);
+                        },
+                        MsgFromDiscord::LiveServer(server) => {
+                            app_state.live_servers.insert(server.id, server);
+                        },
+                        MsgFromDiscord::ReadState(read_state) => {
+                            app_state.set_read_state(read_state);
+                        },
+                        MsgFromDiscord::Read(channel_id, message_id) => {
+                            app_state.set_read(channel_id, message_id);
+                        },
+                        MsgFromDiscord::MemberUpdate { server_id, user, roles, nick } => {
+                            app_state.update_member(server_id, user, roles, nick);
+                        },
+                        MsgFromDiscord::Member(server_id, member) => {
+                            app_state.add_member(server_id, member);
+                        },
+                        MsgFromDiscord::MemberRemove(server_id, user_id) => {
+                            app_state.remove_member(server_id, user_id);
+                        },
+                        MsgFromDiscord::RoleUpdate(server_id, role) => {
+                            app_state.update_role(server_id, role);
+                        },
+                        MsgFromDiscord::RoleDelete(server_id, role_id) => {
+                            app_state.delete_role(server_id, role_id);
+                        },
                         MsgFromDiscord::ChatMsg(message) => {
                             app_state.store_message(message);
+                        },
+                        MsgFromDiscord::History(channel_id, messages, reached_start) => {
+                            app_state.store_history(channel_id, messages, reached_start);
+                        },
+                        MsgFromDiscord::MessageUpdate { channel_id, id, content, edited_timestamp } => {
+                            app_state.update_message(channel_id, id, content, edited_timestamp);
+                        },
+                        MsgFromDiscord::MessageDelete(channel_id, id) => {
+                            app_state.delete_messages(channel_id, &[id]);
+                        },
+                        MsgFromDiscord::MessageDeleteBulk(channel_id, ids) => {
+                            app_state.delete_messages(channel_id, &ids);
+                        },
+                        MsgFromDiscord::ConnectionState(state) => {
+                            app_state.set_connection_state(state);
+                        },
+                        MsgFromDiscord::GatewayError(error) => {
+                            app_state.notify_error(format!("Connection problem: {}", error));
+                        },
+                        MsgFromDiscord::Error { request, error } => {
+                            app_state.handle_error(request, error);
+                        },
+                        MsgFromDiscord::EchoResponse(text) => {
+                            app_state.notify(text);
                         },
 						MsgFromDiscord::Exit => {println!("Got exit msg"); break;},
                         _ => {
-                            app_state.messages.push(MockMessage{
-                                username: String::from("DiscordProvider"),
-                                content: String::from(format!("{:?}", message)),
-                            })
+                            app_state.notify(format!("DiscordProvider: {:?}", message));
                         }
                     }
                 }
@@ -544,6 +2209,7 @@ fn main() {
     let mut t = term.lock().unwrap();
     t.show_cursor().unwrap();
     t.clear().unwrap();
+    notify::restore_title().ok();
     std::process::exit(0);
 }
 
@@ -552,7 +2218,7 @@ fn draw(t: &mut Terminal<RawBackend>, state: &AppState) {
     let channel_name = "temp1";
 
     Group::default().direction(Direction::Vertical)
-        .sizes(&[Size::Min(1), Size::Fixed(1)])
+        .sizes(&[Size::Min(1), Size::Fixed(1), Size::Fixed(1)])
         .render(t, &size, |t, chunks| {
             Group::default()
                 .direction(Direction::Horizontal)
@@ -561,37 +2227,111 @@ fn draw(t: &mut Terminal<RawBackend>, state: &AppState) {
                     draw_left(t, state, &chunks[0]);
                     draw_messagePane(t, state, &chunks[1]);
                 });
-            Paragraph::default().text(&format!("Mode: {:?}, Scroll:{}", state.mode, state.scroll_pos)).render(t, &chunks[1]);
+            draw_notice(t, state, &chunks[1]);
+            if state.mode == Mode::Fzf {
+                draw_switcher(t, state, &chunks[0]);
+            }
+            if state.mode == Mode::Mentions {
+                draw_inbox(t, state, &chunks[0]);
+            }
+            Paragraph::default().text(&format!("Mode: {:?}, Scroll:{}, {}{}", state.mode, state.scroll_pos, state.connection, if state.dnd { ", do not disturb" } else { "" })).render(t, &chunks[2]);
         });
 
     t.draw();
+    if let (Mode::TextInput, Some((x, y))) = (&state.mode, state.cursor.get()) {
+        let mut stdout = io::stdout();
+        write!(stdout, "{}", termion::cursor::Goto(x + 1, y + 1)).ok();
+        stdout.flush().ok();
+    }
+}
+
+fn draw_switcher(t: &mut Terminal<RawBackend>, state: &AppState, area: &Rect) {
+    let mut lines = vec![format!("> {}_", state.switcher.query.text())];
+    lines.extend(state.switch_targets().into_iter().map(|target| target.label));
+    let height = min(lines.len() as u16, 15) + 2;
+    Popup::new("Jump to (Enter to go, Esc to cancel)", &lines)
+        .select(Some(state.switcher.selected + 1))
+        .render(t, &popup::centered(area, area.width / 2, height));
+}
+
+fn draw_inbox(t: &mut Terminal<RawBackend>, state: &AppState, area: &Rect) {
+    let mut lines = state.inbox.lines();
+    let selected = if lines.is_empty() {
+        lines.push(String::from("Nothing has mentioned you yet"));
+        None
+    } else {
+        Some(state.inbox.selected)
+    };
+    Popup::new("Mentions (Enter to go, Esc to close)", &lines)
+        .select(selected)
+        .render(t, &popup::centered(area, area.width * 3 / 4, area.height * 3 / 4));
+}
+
+fn draw_notice(t: &mut Terminal<RawBackend>, state: &AppState, area: &Rect) {
+    if let Some(notice) = state.notices.last() {
+        let color = if notice.error { Color::Red } else { Color::Yellow };
+        let more = if state.notices.len() > 1 { format!(" (+{} more, Esc to dismiss)", state.notices.len() - 1) } else { String::new() };
+        Paragraph::default()
+            .text(&format!("{}{}", notice.text, more))
+            .raw(true)
+            .style(Style::default().fg(color))
+            .render(t, area);
+    }
 }
 
 fn draw_messagePane(t: &mut Terminal<RawBackend>, state: &AppState, area: &Rect) {
     let style = Style::default().fg(Color::Yellow);
     let mut channel_name = "temp2";
 
+    // The input grows with what's typed, up to the configured height
+    let input_rows = min(max(state.input.line_count(), 1), state.config.input_max_height) as u16;
+    let sizes = match state.mode {
+        Mode::TextInput => [Size::Min(1), Size::Fixed(input_rows + 2)],
+        _ => [Size::Percent(90), Size::Min(0)],
+    };
+
     Group::default()
         .direction(Direction::Vertical)
-        .sizes(&[Size::Percent(90), Size::Min(0)])
+        .sizes(&sizes)
         .render(t, area, |t, chunks| {
             let active_server = &state.servers[state.active_server];
             let mut msgs: Vec<discord::model::Message> = vec!();
+            let mut loading = false;
+            let mut selected = None;
+            let mut divider = None;
 
             if (active_server.channels.len() > 0) {
                 let active_channel = &active_server.channels[active_server.active_channel];
                 msgs = active_channel.messages.clone();
                 channel_name = &active_channel.name[..];
+                loading = active_channel.loading_older;
+                divider = active_channel.divider;
+                selected = state.selected.and_then(|id| msgs.iter().position(|msg| msg.id == id));
             }
 
-            let n = msgs.len();
             let nm = (chunks[0].height as usize).checked_sub(2).unwrap_or(0);
-            let left_bound = n.checked_sub(nm+state.scroll_pos).unwrap_or(0);
+            let width = (chunks[0].width as usize).checked_sub(2).unwrap_or(0);
+            state.chat_size.set((width, nm));
             
+            let names = state.names();
             ChatWidget::new(&msgs.to_vec())
 				.scroll(state.scroll_pos)
+				.loading(loading)
+				.select(selected)
+				.names(&names)
+				.divider(divider)
+				.clock(state.config.clock)
                 .block(Block::default().borders(Borders::ALL).title(&format!("#{}", channel_name)[..]))
                 .render(t, &chunks[0]);
+
+            if state.show_details {
+                if let Some(msg) = state.selected_message() {
+                    let details = message_details(msg);
+                    let height = details.len() as u16 + 2;
+                    Popup::new("Message details", &details)
+                        .render(t, &popup::centered(&chunks[0], chunks[0].width * 3 / 4, height));
+                }
+            }
 /*
             let msgs = msgs[left_bound..n].iter().map( |msg| {
                 Item::StyledData(
@@ -610,17 +2350,39 @@ fn draw_messagePane(t: &mut Terminal<RawBackend>, state: &AppState, area: &Rect)
                 Mode::TextInput => {
               let help = match state.mode_stack.last().unwrap() {
                &Mode::Command => String::from("Command"),
+               _ if state.reacting.is_some() => String::from("React with (emoji or name:id)"),
+               _ if state.editing.is_some() => String::from("Edit message (empty to delete)"),
                &Mode::Normal => format!("Message #{}", channel_name),
                x => format!("Input for {:?}", x)};
+                    let block = Block::default().borders(Borders::ALL).title(help.as_ref());
+                    let inner = block.inner(&chunks[1]);
+                    let (rows, (column, row)) = state.input.view(inner.width as usize, inner.height as usize);
+                    state.cursor.set(Some((inner.left() + column as u16, inner.top() + row as u16)));
                      Paragraph::default()
-                        .text(&state.content[..])
-                        .block(Block::default().borders(Borders::ALL).title(help.as_ref()))
+                        .text(&rows.join("\n"))
+                        .raw(true)
+                        .block(block)
                         .render(t, &chunks[1]);
+
+                    // Candidates pop up just above the input
+                    if let Some(ref completion) = state.completion {
+                        let labels = completion.labels();
+                        let height = min(labels.len() as u16 + 2, min(10, chunks[1].y));
+                        let width = min(40, chunks[1].width);
+                        let area = Rect { x: chunks[1].x, y: chunks[1].y - height, width, height };
+                        Popup::new("Complete", &labels)
+                            .select(Some(completion.index))
+                            .render(t, &area);
+                    }
                 }
                 _ => {
                     List::new(match state.mode {
-                        Mode::Normal => vec!["c - Select Channel", "s - Select Server", "i - Insert Message", ": - Command"],
+                        Mode::Normal => vec!["c - Select Channel", "s - Select Server", "/ - Jump To", "M-a - Next Unread", "@ - Mentions", "i - Insert Message", "v - Select Message", "e/d - Edit/Delete Last", "^X - Compose in $EDITOR", ": - Command"],
+                        Mode::ConfirmDelete => vec!["Delete the highlighted message?", "y - Delete", "n - Cancel"],
+                        Mode::MessageSelect => vec!["j/k - Move", "r - Reply, y - Copy, + - React", "e/d - Edit/Delete", "o - Open Links, Enter - Details", "Esc - Back"],
                         Mode::ChannelSelect => vec!["j/k - Move", "Tab - Select Server", "Enter - Accept"],
+                        Mode::Fzf => vec!["Type to search", "Up/Down - Move", "Enter - Go", "Esc - Cancel"],
+                        Mode::Mentions => vec!["j/k - Move", "Enter - Go To Message", "Esc - Close"],
                         Mode::ServerSelect => vec!["j/k - Move", "Tab - Select Channel", "Enter - Accept"],
                         _ => vec![]
                     }.iter().map(|x| Item::Data(x)))
@@ -631,6 +2393,19 @@ fn draw_messagePane(t: &mut Terminal<RawBackend>, state: &AppState, area: &Rect)
         });
 }
 
+/// Pings first, then how many messages came in, or just a dot if Discord says there's something new
+fn badge(unread: bool, count: usize, mentions: usize) -> String {
+    if mentions > 0 {
+        format!(" (@{})", mentions)
+    } else if count > 0 {
+        format!(" ({})", count)
+    } else if unread {
+        String::from(" •")
+    } else {
+        String::new()
+    }
+}
+
 fn draw_left(t: &mut Terminal<RawBackend>, state: &AppState, area: &Rect) {
     Group::default()
         .direction(Direction::Vertical)
@@ -643,7 +2418,13 @@ fn draw_left(t: &mut Terminal<RawBackend>, state: &AppState, area: &Rect) {
                     Mode::ServerSelect => selectedBorder,
                     _ => defaultBorder
                 }))
-                .items(&state.servers)
+                .items(&state.servers.iter().map(|server| {
+                    let channels = &server.channels;
+                    let unread = channels.iter().any(Channel::is_unread);
+                    let count = channels.iter().map(|channel| channel.unread).sum();
+                    let mentions = channels.iter().map(|channel| channel.mentions).sum();
+                    format!("{}{}", server.server_info.name, badge(unread, count, mentions))
+                }).collect::<Vec<_>>())
                 .select(state.active_server)
                 .highlight_style(Style::default().fg(Color::Green).modifier(Modifier::Bold))
                 .highlight_symbol(
@@ -658,7 +2439,9 @@ fn draw_left(t: &mut Terminal<RawBackend>, state: &AppState, area: &Rect) {
                     Mode::ChannelSelect => selectedBorder,
                     _ => defaultBorder
                 }))
-                .items(&state.servers[state.active_server].channels)
+                .items(&state.servers[state.active_server].channels.iter().map(|channel| {
+                    format!("{}{}", channel.name, badge(channel.is_unread(), channel.unread, channel.mentions))
+                }).collect::<Vec<_>>())
                 .select(state.servers[state.active_server].active_channel)
                 .highlight_style(Style::default().fg(Color::Green).modifier(Modifier::Bold))
                 .highlight_symbol(
@@ -670,3 +2453,90 @@ fn draw_left(t: &mut Terminal<RawBackend>, state: &AppState, area: &Rect) {
 
         });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use discord::model::{ChannelId, MessageId};
+
+    fn page(first: u64, last: u64) -> Vec<Message> {
+        (first..last + 1).map(|id| mock_provider::message(100, id, (10, "ferris"), "hi", 0)).collect()
+    }
+
+    /// What store_history does with a page
+    fn receive(channel: &mut Channel, messages: Vec<Message>, reached_start: bool) {
+        channel.fill_gap(&messages, reached_start);
+        channel.merge_messages(messages);
+    }
+
+    fn requested_after(requests: &chan::Receiver<MsgToDiscord>) -> Option<u64> {
+        match requests.recv() {
+            Some(MsgToDiscord::GetMessages(_, GetMessages::After(id), BACKFILL_LIMIT)) => Some(id.0),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn gap_follows_the_pages_after_a_jump() {
+        let (provider, requests) = chan::async();
+        let mut channel = Channel::new(ChannelId(100), String::from("general"));
+        channel.gap_after = Some(MessageId(100));
+
+        // The page around the message jumped to
+        receive(&mut channel, page(90, 110), false);
+        assert_eq!(channel.gap_after, Some(MessageId(110)));
+
+        // A full page after it, there may be more
+        channel.request_gap(&provider);
+        assert_eq!(requested_after(&requests), Some(110));
+        receive(&mut channel, page(111, 310), false);
+        assert_eq!(channel.gap_after, Some(MessageId(310)));
+        assert!(!channel.backfilling);
+
+        // A short one, that was everything
+        channel.request_gap(&provider);
+        assert_eq!(requested_after(&requests), Some(310));
+        receive(&mut channel, page(311, 320), false);
+        assert_eq!(channel.gap_after, None);
+        assert!(!channel.backfilling);
+        assert_eq!(channel.messages.len(), 231);
+    }
+
+    #[test]
+    fn backfill_closes_the_gap_when_it_reaches_what_we_have() {
+        let (provider, _requests) = chan::async();
+        let mut channel = Channel::new(ChannelId(100), String::from("general"));
+        receive(&mut channel, page(201, 300), false);
+        // Said while the backfill was on its way
+        receive(&mut channel, page(500, 500), false);
+        channel.gap_after = Some(MessageId(300));
+        channel.request_gap(&provider);
+
+        receive(&mut channel, page(301, 500), false);
+        assert_eq!(channel.gap_after, None);
+        assert!(!channel.backfilling);
+    }
+
+    #[test]
+    fn most_recent_page_closes_the_gap_when_it_overlaps() {
+        let mut channel = Channel::new(ChannelId(100), String::from("general"));
+        receive(&mut channel, page(500, 500), false);
+        channel.gap_after = Some(MessageId(110));
+
+        receive(&mut channel, page(451, 500), false);
+        assert_eq!(channel.gap_after, None);
+    }
+
+    #[test]
+    fn older_pages_leave_the_gap_alone() {
+        let (provider, _requests) = chan::async();
+        let mut channel = Channel::new(ChannelId(100), String::from("general"));
+        channel.gap_after = Some(MessageId(100));
+        receive(&mut channel, page(90, 110), false);
+        channel.request_gap(&provider);
+
+        receive(&mut channel, page(40, 89), true);
+        assert_eq!(channel.gap_after, Some(MessageId(110)));
+        assert!(channel.backfilling);
+    }
+}
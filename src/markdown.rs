@@ -0,0 +1,318 @@
+use tui::style::{Color, Modifier, Style};
+
+/// A run of text that shares one style
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub text: String,
+    pub style: Style,
+}
+
+/// One source line of a message. Code block lines get their background
+/// stretched across the whole row when drawn.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub spans: Vec<Span>,
+    pub code: bool,
+}
+
+pub fn code_block_style() -> Style {
+    Style::default().fg(Color::White).bg(Color::Black)
+}
+
+pub fn mention_style() -> Style {
+    Style::default().fg(Color::LightBlue).modifier(Modifier::Bold)
+}
+
+/// Turns what's inside `<...>` into readable text, or None to leave it be
+pub type Resolve<'a> = &'a Fn(&str) -> Option<String>;
+
+/// Everything that can be switched on and off inside a line
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Format {
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    strike: bool,
+    spoiler: bool,
+    code: bool,
+    quote: bool,
+}
+
+impl Format {
+    /// tui only does one modifier at a time, so the strongest one wins
+    fn style(&self, base: Style) -> Style {
+        if self.code {
+            return Style::default().fg(Color::Yellow).bg(Color::Black);
+        }
+        if self.spoiler {
+            return Style::default().fg(Color::DarkGray).bg(Color::DarkGray);
+        }
+        let mut style = base;
+        if self.quote {
+            style = style.fg(Color::Cyan);
+        }
+        if self.bold {
+            style.modifier(Modifier::Bold)
+        } else if self.italic {
+            style.modifier(Modifier::Italic)
+        } else if self.underline {
+            style.modifier(Modifier::Underline)
+        } else if self.strike {
+            style.modifier(Modifier::CrossedOut)
+        } else {
+            style
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Toggle {
+    Bold,
+    Italic,
+    Underline,
+    Strike,
+    Spoiler,
+}
+
+impl Toggle {
+    fn is_set(&self, format: &Format) -> bool {
+        match *self {
+            Toggle::Bold => format.bold,
+            Toggle::Italic => format.italic,
+            Toggle::Underline => format.underline,
+            Toggle::Strike => format.strike,
+            Toggle::Spoiler => format.spoiler,
+        }
+    }
+    fn flip(&self, format: &mut Format) {
+        match *self {
+            Toggle::Bold => format.bold = !format.bold,
+            Toggle::Italic => format.italic = !format.italic,
+            Toggle::Underline => format.underline = !format.underline,
+            Toggle::Strike => format.strike = !format.strike,
+            Toggle::Spoiler => format.spoiler = !format.spoiler,
+        }
+    }
+}
+
+/// Longest delimiters first, so `**` isn't read as two `*`
+static DELIMITERS: &'static [(&'static str, Toggle)] = &[
+    ("**", Toggle::Bold),
+    ("__", Toggle::Underline),
+    ("~~", Toggle::Strike),
+    ("||", Toggle::Spoiler),
+    ("*", Toggle::Italic),
+    ("_", Toggle::Italic),
+];
+
+/// Parses Discord's markdown dialect into styled lines, keeping the message's line breaks.
+/// Mentions and custom emoji outside of code go through `resolve`.
+pub fn parse(text: &str, base: Style, resolve: Resolve) -> Vec<Line> {
+    let mut lines = vec![];
+    // Fences split the message into alternating prose and code. An odd one out
+    // was never closed, so it stays in the prose as plain backticks.
+    let fences = text.matches("```").count();
+    let paired = fences - fences % 2;
+    for (i, segment) in text.splitn(paired + 1, "```").enumerate() {
+        if i % 2 == 1 {
+            code_block(segment, &mut lines);
+        } else {
+            prose(segment, base, i > 0, i < paired, resolve, &mut lines);
+        }
+    }
+    if lines.is_empty() {
+        lines.push(Line { spans: vec![], code: false });
+    }
+    lines
+}
+
+/// Just the text, mentions resolved, for places that can't show styling
+pub fn plain(text: &str, resolve: Resolve) -> String {
+    parse(text, Style::default(), resolve)
+        .iter()
+        .map(|line| line.spans.iter().map(|span| &span.text[..]).collect::<String>())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn code_block(segment: &str, lines: &mut Vec<Line>) {
+    // ```rust\n...``` names the language on the first line, drop it
+    let body = match segment.find('\n') {
+        Some(newline) if !segment[..newline].trim().contains(' ') => &segment[newline + 1..],
+        _ => segment,
+    };
+    let body = body.trim_right_matches('\n');
+    for line in body.split('\n') {
+        lines.push(Line {
+            spans: vec![Span { text: String::from(line), style: code_block_style() }],
+            code: true,
+        });
+    }
+}
+
+fn prose(segment: &str, base: Style, after_fence: bool, before_fence: bool, resolve: Resolve, lines: &mut Vec<Line>) {
+    let mut source: Vec<&str> = segment.split('\n').collect();
+    // The line breaks right next to a fence belong to the fence, not to us
+    if before_fence && source.last() == Some(&"") {
+        source.pop();
+    }
+    if after_fence && source.first() == Some(&"") {
+        source.remove(0);
+    }
+
+    let mut quote_rest = false; // `>>> ` quotes everything after it
+    for line in source {
+        let mut format = Format::default();
+        let mut spans = vec![];
+        let mut rest = line;
+        if rest.starts_with(">>> ") {
+            quote_rest = true;
+            rest = &rest[4..];
+        } else if rest.starts_with("> ") {
+            format.quote = true;
+            rest = &rest[2..];
+        }
+        if quote_rest {
+            format.quote = true;
+        }
+        if format.quote {
+            spans.push(Span { text: String::from("▎ "), style: Style::default().fg(Color::DarkGray) });
+        }
+        inline(rest, format, base, resolve, &mut spans);
+        lines.push(Line { spans, code: false });
+    }
+}
+
+fn inline(line: &str, format: Format, base: Style, resolve: Resolve, spans: &mut Vec<Span>) {
+    let chars: Vec<char> = line.chars().collect();
+    let mut format = format;
+    let mut text = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        if c == '\\' && i + 1 < chars.len() && "*_~|`\\>".contains(chars[i + 1]) {
+            text.push(chars[i + 1]);
+            i += 2;
+            continue;
+        }
+
+        if c == '`' {
+            // Empty code isn't code, `` is just two backticks
+            if let Some(end) = find(&chars, i + 1, &['`']).and_then(|end| if end > i + 1 { Some(end) } else { None }) {
+                flush(&mut text, format, base, spans);
+                let code: String = chars[i + 1..end].iter().collect();
+                let code_format = Format { code: true, ..format };
+                spans.push(Span { text: code, style: code_format.style(base) });
+                i = end + 1;
+                continue;
+            }
+        }
+
+        if c == '<' {
+            let resolved = find(&chars, i + 1, &['>']).and_then(|end| {
+                let token: String = chars[i + 1..end].iter().collect();
+                resolve(&token).map(|name| (end, name))
+            });
+            if let Some((end, name)) = resolved {
+                flush(&mut text, format, base, spans);
+                spans.push(Span { text: name, style: mention_style() });
+                i = end + 1;
+                continue;
+            }
+        }
+
+        let delimiter = DELIMITERS.iter().find(|&&(token, _)| {
+            token.chars().enumerate().all(|(offset, t)| chars.get(i + offset) == Some(&t))
+        });
+        if let Some(&(token, toggle)) = delimiter {
+            let token: Vec<char> = token.chars().collect();
+            let len = token.len();
+            let open = !toggle.is_set(&format);
+            // `_` only counts at word edges, so snake_case stays as it is
+            let at_edge = token != ['_'] || if open {
+                i == 0 || !chars[i - 1].is_alphanumeric()
+            } else {
+                chars.get(i + 1).map_or(true, |c| !c.is_alphanumeric())
+            };
+            // Only open if it gets closed again on this line
+            if at_edge && (!open || find(&chars, i + len + 1, &token).is_some()) {
+                flush(&mut text, format, base, spans);
+                toggle.flip(&mut format);
+                i += len;
+                continue;
+            }
+        }
+
+        text.push(c);
+        i += 1;
+    }
+    flush(&mut text, format, base, spans);
+}
+
+/// Index of the next `token` at or after `from`
+fn find(chars: &[char], from: usize, token: &[char]) -> Option<usize> {
+    if token.len() > chars.len() {
+        return None;
+    }
+    (from..chars.len() - token.len() + 1).find(|&i| &chars[i..i + token.len()] == token)
+}
+
+fn flush(text: &mut String, format: Format, base: Style, spans: &mut Vec<Span>) {
+    if !text.is_empty() {
+        spans.push(Span { text: text.clone(), style: format.style(base) });
+        text.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_names(_: &str) -> Option<String> {
+        None
+    }
+
+    fn texts(lines: &[Line]) -> Vec<String> {
+        lines.iter().map(|line| line.spans.iter().map(|span| &span.text[..]).collect()).collect()
+    }
+
+    #[test]
+    fn unclosed_fence_is_plain_text() {
+        assert_eq!(plain("a ```b", &no_names), "a ```b");
+        let lines = parse("x\n```\ny", Style::default(), &no_names);
+        assert_eq!(texts(&lines), vec!["x", "```", "y"]);
+        assert!(lines.iter().all(|line| !line.code));
+    }
+
+    #[test]
+    fn closed_fence_then_unclosed_one() {
+        let lines = parse("```\ncode\n```\ntail ```", Style::default(), &no_names);
+        assert_eq!(texts(&lines), vec!["code", "tail ```"]);
+        assert_eq!(lines.iter().map(|line| line.code).collect::<Vec<_>>(), vec![true, false]);
+    }
+
+    #[test]
+    fn fence_drops_the_language() {
+        let lines = parse("```rust\nlet x = 1;\n```", Style::default(), &no_names);
+        assert_eq!(texts(&lines), vec!["let x = 1;"]);
+        assert!(lines[0].code);
+    }
+
+    #[test]
+    fn inline_code_keeps_mentions_and_markup() {
+        let resolve = |token: &str| if token == "@1" { Some(String::from("@anna")) } else { None };
+        assert_eq!(plain("<@1> `<@1> **x**` **y**", &resolve), "@anna <@1> **x** y");
+    }
+
+    #[test]
+    fn empty_backticks_stay() {
+        assert_eq!(plain("a `` b", &no_names), "a `` b");
+    }
+
+    #[test]
+    fn underscores_inside_words_stay() {
+        assert_eq!(plain("snake_case_name and _this_", &no_names), "snake_case_name and this");
+    }
+}
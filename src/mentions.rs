@@ -0,0 +1,78 @@
+use discord::model::{ChannelId, Message, RoleId, User, UserId};
+use tui::style::Color;
+
+use std::collections::HashMap;
+
+/// What the ids in `<@id>`, `<@&id>`, `<#id>` and `<:name:id>` stand for
+#[derive(Default)]
+pub struct Names {
+    pub users: HashMap<UserId, String>, // server nickname, or username without one
+    pub colors: HashMap<UserId, Color>, // from their highest colored role
+    pub roles: HashMap<RoleId, String>,
+    pub channels: HashMap<ChannelId, String>,
+    pub me: Option<UserId>,
+    pub my_roles: Vec<RoleId>,
+}
+
+impl Names {
+    /// Readable text for what's between the angle brackets, None if it isn't a mention
+    pub fn resolve(&self, token: &str) -> Option<String> {
+        // Only a well-formed id is a mention, <#include <stdio.h> is just text
+        if token.starts_with("@&") {
+            let role = RoleId(id(&token[2..])?);
+            return Some(format!("@{}", self.roles.get(&role).map_or("deleted-role", |name| &name[..])));
+        }
+        if token.starts_with('@') {
+            // <@!id> is the same person, mentioned by nickname
+            let user = UserId(id(token[1..].trim_left_matches('!'))?);
+            return Some(format!("@{}", self.users.get(&user).map_or("unknown-user", |name| &name[..])));
+        }
+        if token.starts_with('#') {
+            let channel = ChannelId(id(&token[1..])?);
+            return Some(format!("#{}", self.channels.get(&channel).map_or("deleted-channel", |name| &name[..])));
+        }
+        // <:name:id>, or <a:name:id> when it's animated
+        let emoji = token.trim_left_matches('a');
+        if emoji.starts_with(':') {
+            let mut parts = emoji[1..].splitn(2, ':');
+            if let (Some(name), Some(emoji_id)) = (parts.next(), parts.next()) {
+                if !name.is_empty() && id(emoji_id).is_some() {
+                    return Some(format!(":{}:", name));
+                }
+            }
+        }
+        None
+    }
+
+    /// What to call someone: their nickname here if we know it
+    pub fn display_name<'a>(&'a self, user: &'a User) -> &'a str {
+        self.users.get(&user.id).map_or(&user.name[..], |name| &name[..])
+    }
+
+    /// Pinged us directly, through one of our roles, or with @everyone/@here
+    pub fn mentions_me(&self, msg: &Message) -> bool {
+        msg.mention_everyone
+            || self.me.map_or(false, |me| msg.mentions.iter().any(|user| user.id == me))
+            || msg.mention_roles.iter().any(|role| self.my_roles.contains(role))
+    }
+}
+
+fn id(digits: &str) -> Option<u64> {
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// Bots, and webhooks, whose made-up authors come flagged as bots too
+pub fn is_bot(user: &User) -> bool {
+    user.bot
+}
+
+/// Discord keeps role colors as 0xRRGGBB, with 0 meaning no color
+pub fn role_color(color: u64) -> Option<Color> {
+    if color == 0 {
+        return None;
+    }
+    Some(Color::Rgb((color >> 16) as u8, (color >> 8) as u8, color as u8))
+}
@@ -0,0 +1,461 @@
+use discord::GetMessages;
+use discord::model::*;
+use chan::{Sender, Receiver};
+use chrono::{Duration, Utc};
+use serde_json;
+use thread;
+use std::cmp::min;
+use std::time::{Duration as StdDuration, Instant};
+
+use discord_provider::{ChatBackend, ConnectionState, MsgFromDiscord, MsgToDiscord, ProviderError};
+
+/// Fake servers and their channels, as (id, name)
+static SERVERS: &'static [(u64, &'static str, &'static [(u64, &'static str)])] = &[
+    (1, "Rustaceans", &[(100, "general"), (101, "help"), (102, "off-topic")]),
+    (2, "Terminal Club", &[(200, "lobby"), (201, "screenshots")]),
+];
+
+static USERS: &'static [(u64, &'static str)] = &[
+    (10, "ferris"),
+    (11, "corro"),
+    (12, "bors"),
+];
+
+/// Fake DMs as (channel id, recipient user ids), more than one makes it a group
+static DIRECT_MESSAGES: &'static [(u64, &'static [u64])] = &[
+    (300, &[10]),
+    (301, &[10, 11]),
+];
+
+static ME: (u64, &'static str) = (1, "you");
+
+/// Users that are bots, they get a [BOT] tag
+static BOTS: &'static [u64] = &[12];
+
+/// Roles as (server id, role id, name, color, position)
+static ROLES: &'static [(u64, u64, &'static str, u64, i64)] = &[
+    (1, 900, "maintainers", 0xE67E22, 2),
+    (1, 901, "contributors", 0x3498DB, 1),
+    (2, 902, "regulars", 0x2ECC71, 1),
+];
+
+/// Who has which roles, as (user id, role ids, nickname), roles only count in their own server
+static MEMBERS: &'static [(u64, &'static [u64], Option<&'static str>)] = &[
+    (10, &[900, 901, 902], Some("Ferris the Crab")),
+    (11, &[901], None),
+];
+
+/// Custom emoji as (server id, emoji id, name)
+static EMOJIS: &'static [(u64, u64, &'static str)] = &[
+    (1, 500, "ferrisparty"),
+    (1, 501, "rustacean"),
+    (2, 502, "blinking_cursor"),
+];
+
+/// Channels we can read but not post in, to exercise send errors
+static READ_ONLY: &'static [u64] = &[201];
+
+static LINES: &'static [&'static str] = &[
+    "has anyone tried the new borrow checker yet?",
+    "it compiles, ship it",
+    "I think you want an Rc<RefCell<_>> there",
+    "r+",
+    "the lifetimes were wrong the whole time",
+    "cargo clippy has opinions about this",
+    "works on my machine",
+    "can we get a screenshot of the new layout?",
+    "who broke the build",
+    "not me",
+    "<@1> could you take a look at this?",
+    "moved the discussion to <#101> <:ferrisparty:500>",
+    "<@&900> release is out, thanks <@10>!",
+];
+
+/// How many messages every fake channel pretends to have in its history
+const HISTORY_LEN: u64 = 300;
+/// Message ids in a channel are `channel * ID_SPACE + n`
+const ID_SPACE: u64 = 10_000;
+/// How often someone says something in a random channel
+const CHATTER_SECS: u64 = 5;
+/// Every so often the last thing said gets edited or deleted instead
+const EDIT_EVERY: u64 = 5;
+const DELETE_EVERY: u64 = 7;
+
+/// Scripted, in-memory stand-in for `DiscordProvider` so the UI can run offline
+pub struct MockProvider {
+    tx: Sender<MsgFromDiscord>,
+    rx: Receiver<MsgToDiscord>,
+}
+
+impl MockProvider {
+    pub fn init(channel: (Sender<MsgFromDiscord>, Receiver<MsgToDiscord>)) -> Self {
+        MockProvider {
+            tx: channel.0,
+            rx: channel.1,
+        }
+    }
+}
+
+impl ChatBackend for MockProvider {
+    fn start_provider(self: Box<Self>) {
+        let ui_sender = self.tx;
+        let ui_reciever = self.rx;
+        let mut sent: u64 = 0;
+        let mut last_chatter = Instant::now();
+
+        ui_sender.send(MsgFromDiscord::ConnectionState(ConnectionState::Connected));
+        ui_sender.send(MsgFromDiscord::CurrentUser(current_user()));
+        for server in live_servers() {
+            ui_sender.send(MsgFromDiscord::LiveServer(server));
+        }
+        ui_sender.send(MsgFromDiscord::ReadState(read_state()));
+        loop {
+            chan_select! {
+                default => {
+                    if last_chatter.elapsed() >= StdDuration::from_secs(CHATTER_SECS) {
+                        last_chatter = Instant::now();
+                        let previous = chatter(sent);
+                        sent += 1;
+                        if sent % EDIT_EVERY == 0 {
+                            ui_sender.send(MsgFromDiscord::MessageUpdate {
+                                channel_id: previous.channel_id,
+                                id: previous.id,
+                                content: Some(format!("{} (typo fixed)", previous.content)),
+                                edited_timestamp: Some(Utc::now().into()),
+                            });
+                        } else if sent % DELETE_EVERY == 0 {
+                            ui_sender.send(MsgFromDiscord::MessageDelete(previous.channel_id, previous.id));
+                        } else {
+                            ui_sender.send(MsgFromDiscord::ChatMsg(chatter(sent)));
+                        }
+                    }
+                    thread::sleep(StdDuration::from_millis(10));
+                },
+                ui_reciever.recv() -> val => {
+                    let message = match val {
+                        Some(message) => message,
+                        None => return,
+                    };
+                    match message {
+                        MsgToDiscord::GetServers => {
+                            ui_sender.send(MsgFromDiscord::Servers(servers()));
+                            ui_sender.send(MsgFromDiscord::PrivateChannels(private_channels()));
+                        },
+                        MsgToDiscord::GetChannels(server_id) => {
+                            ui_sender.send(MsgFromDiscord::Channels(server_id, channels(server_id)));
+                        },
+                        MsgToDiscord::GetMessages(channel, what, count) => {
+                            // A short Around page only means the message is near the newest end,
+                            // it says nothing about how far back the channel goes
+                            let backwards = match what {
+                                GetMessages::MostRecent | GetMessages::Before(_) => true,
+                                GetMessages::Around(_) | GetMessages::After(_) => false,
+                            };
+                            let messages = history(channel, what, count);
+                            let reached_start = backwards && messages.len() < count;
+                            ui_sender.send(MsgFromDiscord::History(channel, messages, reached_start));
+                        },
+                        MsgToDiscord::SendMessage(channel, content) => {
+                            if READ_ONLY.contains(&channel.0) {
+                                ui_sender.send(MsgFromDiscord::Error {
+                                    request: MsgToDiscord::SendMessage(channel, content),
+                                    error: ProviderError::MissingPermissions,
+                                });
+                            } else {
+                                sent += 1;
+                                let id = channel.0 * ID_SPACE + HISTORY_LEN + sent;
+                                ui_sender.send(MsgFromDiscord::ChatMsg(message(channel.0, id, ME, &content, 0)));
+                            }
+                        },
+                        MsgToDiscord::EditMessage(channel, id, content) => {
+                            ui_sender.send(MsgFromDiscord::MessageUpdate {
+                                channel_id: channel,
+                                id,
+                                content: Some(content),
+                                edited_timestamp: Some(Utc::now().into()),
+                            });
+                        },
+                        MsgToDiscord::DeleteMessage(channel, id) => {
+                            ui_sender.send(MsgFromDiscord::MessageDelete(channel, id));
+                        },
+                        MsgToDiscord::AddReaction(_, _, emoji) => {
+                            ui_sender.send(MsgFromDiscord::EchoResponse(format!("Reacted with {}", emoji)));
+                        },
+                        MsgToDiscord::SendDirectMessage(user, content) => {
+                            if !USERS.iter().any(|&(id, _)| id == user.0) {
+                                ui_sender.send(MsgFromDiscord::Error {
+                                    request: MsgToDiscord::SendDirectMessage(user, content),
+                                    error: ProviderError::Other(String::from("Unknown User")),
+                                });
+                            } else {
+                                let channel = direct_message_with(user.0);
+                                ui_sender.send(MsgFromDiscord::PrivateChannels(vec![private_channel(channel, &[user.0])]));
+                                sent += 1;
+                                let id = channel * ID_SPACE + HISTORY_LEN + sent;
+                                ui_sender.send(MsgFromDiscord::ChatMsg(message(channel, id, ME, &content, 0)));
+                            }
+                        },
+                        MsgToDiscord::SetNickname(server_id, nick) => {
+                            ui_sender.send(MsgFromDiscord::MemberUpdate {
+                                server_id,
+                                user: serde_json::from_value(user_json(ME)).expect("Mock user doesn't match the discord model"),
+                                roles: vec![],
+                                nick: if nick.is_empty() { None } else { Some(nick) },
+                            });
+                        },
+                        MsgToDiscord::GetMember(server_id, user_id) => {
+                            match USERS.iter().chain(Some(&ME)).find(|&&(id, _)| id == user_id.0) {
+                                Some(&user) => {
+                                    let member = serde_json::from_value(member_json(server_id.0, user))
+                                        .expect("Mock member doesn't match the discord model");
+                                    ui_sender.send(MsgFromDiscord::Member(server_id, member));
+                                },
+                                None => ui_sender.send(MsgFromDiscord::Error {
+                                    request: MsgToDiscord::GetMember(server_id, user_id),
+                                    error: ProviderError::Other(String::from("Unknown Member")),
+                                }),
+                            }
+                        },
+                        MsgToDiscord::Ack(..) => (),
+                        MsgToDiscord::Logout => {
+                            ui_sender.send(MsgFromDiscord::Exit);
+                            return;
+                        },
+                        MsgToDiscord::Echo(message) => {
+                            ui_sender.send(MsgFromDiscord::EchoResponse(message));
+                        },
+                    }
+                },
+            }
+        }
+    }
+}
+
+fn servers() -> Vec<ServerInfo> {
+    SERVERS.iter().map(|&(id, name, _)| ServerInfo {
+        id: ServerId(id),
+        name: String::from(name),
+        icon: None,
+        owner: false,
+        permissions: permissions::Permissions::empty(),
+    }).collect()
+}
+
+fn channels(server_id: ServerId) -> Vec<PublicChannel> {
+    let channels = SERVERS.iter()
+        .find(|&&(id, _, _)| id == server_id.0)
+        .map(|&(_, _, channels)| channels)
+        .unwrap_or(&[]);
+
+    channels.iter().enumerate().map(|(position, &(id, name))| {
+        serde_json::from_value(json!({
+            "id": id.to_string(),
+            "guild_id": server_id.0.to_string(),
+            "name": name,
+            "type": 0,
+            "position": position,
+            "permission_overwrites": [],
+            "topic": null,
+            "last_message_id": null
+        })).expect("Mock channel doesn't match the discord model")
+    }).collect()
+}
+
+/// What READY would tell us about each server: everyone's a member of everything
+fn live_servers() -> Vec<LiveServer> {
+    SERVERS.iter().map(|&(id, name, _)| {
+        let members: Vec<_> = USERS.iter().chain(Some(&ME)).map(|&user| member_json(id, user)).collect();
+        let roles: Vec<_> = ROLES.iter()
+            .filter(|&&(server, _, _, _, _)| server == id)
+            .map(|&(_, role_id, role_name, color, position)| json!({
+                "id": role_id.to_string(),
+                "name": role_name,
+                "color": color,
+                "hoist": false,
+                "managed": false,
+                "position": position,
+                "mentionable": true,
+                "permissions": 0
+            }))
+            .collect();
+        let emojis: Vec<_> = EMOJIS.iter()
+            .filter(|&&(server, _, _)| server == id)
+            .map(|&(_, emoji_id, emoji_name)| json!({
+                "id": emoji_id.to_string(),
+                "name": emoji_name,
+                "managed": false,
+                "require_colons": true,
+                "roles": []
+            }))
+            .collect();
+        serde_json::from_value(json!({
+            "id": id.to_string(),
+            "name": name,
+            "owner_id": ME.0.to_string(),
+            "voice_states": [],
+            "roles": roles,
+            "region": "us-east",
+            "presences": [],
+            "member_count": members.len(),
+            "members": members,
+            "joined_at": Utc::now().to_rfc3339(),
+            "icon": null,
+            "large": false,
+            "channels": [],
+            "afk_timeout": 300,
+            "afk_channel_id": null,
+            "verification_level": 0,
+            "emojis": emojis,
+            "features": [],
+            "splash": null,
+            "default_message_notifications": 0,
+            "mfa_level": 0
+        })).expect("Mock server doesn't match the discord model")
+    }).collect()
+}
+
+/// Caught up everywhere except the first DM, which has a couple of things waiting
+fn read_state() -> Vec<ReadState> {
+    DIRECT_MESSAGES.iter().enumerate().map(|(i, &(id, _))| {
+        let behind = if i == 0 { 3 } else { 1 };
+        serde_json::from_value(json!({
+            "id": id.to_string(),
+            "last_message_id": (id * ID_SPACE + HISTORY_LEN - behind).to_string(),
+            "mention_count": behind - 1
+        })).expect("Mock read state doesn't match the discord model")
+    }).collect()
+}
+
+fn private_channels() -> Vec<Channel> {
+    DIRECT_MESSAGES.iter().map(|&(id, recipients)| private_channel(id, recipients)).collect()
+}
+
+fn private_channel(id: u64, recipients: &[u64]) -> Channel {
+    let recipients: Vec<_> = recipients.iter()
+        .filter_map(|&user_id| USERS.iter().find(|&&(uid, _)| uid == user_id))
+        .map(|&user| user_json(user))
+        .collect();
+    let kind = if recipients.len() > 1 { 3 } else { 1 };
+    serde_json::from_value(json!({
+        "id": id.to_string(),
+        "type": kind,
+        "recipients": recipients,
+        "owner_id": ME.0.to_string(),
+        "name": null,
+        "icon": null,
+        "last_message_id": (id * ID_SPACE + HISTORY_LEN - 1).to_string()
+    })).expect("Mock DM doesn't match the discord model")
+}
+
+/// The one-on-one DM with `user`, made up on the spot if the script doesn't have one
+fn direct_message_with(user: u64) -> u64 {
+    DIRECT_MESSAGES.iter()
+        .find(|&&(_, recipients)| recipients == &[user])
+        .map_or(400 + user, |&(id, _)| id)
+}
+
+fn current_user() -> CurrentUser {
+    let mut user = user_json(ME);
+    user["email"] = json!(null);
+    user["verified"] = json!(true);
+    user["mfa_enabled"] = json!(false);
+    serde_json::from_value(user).expect("Mock user doesn't match the discord model")
+}
+
+fn user_json(user: (u64, &str)) -> serde_json::Value {
+    json!({
+        "id": user.0.to_string(),
+        "username": user.1,
+        "discriminator": "0001",
+        "avatar": null,
+        "bot": BOTS.contains(&user.0)
+    })
+}
+
+fn member_json(server_id: u64, user: (u64, &str)) -> serde_json::Value {
+    let (roles, nick) = MEMBERS.iter()
+        .find(|&&(user_id, _, _)| user_id == user.0)
+        .map_or((vec![], None), |&(_, roles, nick)| {
+            let roles: Vec<String> = roles.iter()
+                .filter(|&&role| ROLES.iter().any(|&(server, id, _, _, _)| server == server_id && id == role))
+                .map(|role| role.to_string())
+                .collect();
+            (roles, nick)
+        });
+    json!({
+        "user": user_json(user),
+        "roles": roles,
+        "nick": nick,
+        "joined_at": Utc::now().to_rfc3339(),
+        "mute": false,
+        "deaf": false
+    })
+}
+
+/// Answers a history request the way the API does: newest first, at most `count`
+fn history(channel: ChannelId, what: GetMessages, count: usize) -> Vec<Message> {
+    let count = count as u64;
+    let base = channel.0 * ID_SPACE;
+    let index = |id: MessageId| min(id.0.checked_sub(base).unwrap_or(0), HISTORY_LEN);
+    let (start, end) = match what {
+        GetMessages::MostRecent => (HISTORY_LEN.checked_sub(count).unwrap_or(0), HISTORY_LEN),
+        GetMessages::Before(id) => {
+            let end = index(id);
+            (end.checked_sub(count).unwrap_or(0), end)
+        },
+        GetMessages::After(id) => {
+            let start = min(index(id) + 1, HISTORY_LEN);
+            (start, min(start + count, HISTORY_LEN))
+        },
+        GetMessages::Around(id) => {
+            let start = index(id).checked_sub(count / 2).unwrap_or(0);
+            (start, min(start + count, HISTORY_LEN))
+        },
+    };
+
+    (start..end).rev().map(|i| {
+        // Some of it is ours, so there's something to edit and delete
+        let author = if i % 4 == 0 { ME } else { USERS[i as usize % USERS.len()] };
+        let content = LINES[(i + channel.0) as usize % LINES.len()];
+        let age = ((HISTORY_LEN - i) * 7) as i64;
+        message(channel.0, base + i, author, content, age)
+    }).collect()
+}
+
+/// Someone saying something in one of the fake channels
+fn chatter(n: u64) -> Message {
+    let channels: Vec<u64> = SERVERS.iter()
+        .flat_map(|&(_, _, channels)| channels.iter().map(|&(id, _)| id))
+        .chain(DIRECT_MESSAGES.iter().map(|&(id, _)| id))
+        .collect();
+    let channel = channels[n as usize % channels.len()];
+    let author = USERS[n as usize % USERS.len()];
+    let content = LINES[n as usize % LINES.len()];
+    message(channel, channel * ID_SPACE + HISTORY_LEN + n, author, content, 0)
+}
+
+/// A message as Discord would send it, the tests elsewhere build theirs with this too
+pub fn message(channel: u64, id: u64, author: (u64, &str), content: &str, age_minutes: i64) -> Message {
+    let timestamp = Utc::now() - Duration::minutes(age_minutes);
+    let mentions: Vec<_> = USERS.iter().chain(Some(&ME))
+        .filter(|&&(user_id, _)| content.contains(&format!("<@{}>", user_id)))
+        .map(|&user| user_json(user))
+        .collect();
+    serde_json::from_value(json!({
+        "id": id.to_string(),
+        "channel_id": channel.to_string(),
+        "content": content,
+        "nonce": null,
+        "tts": false,
+        "timestamp": timestamp.to_rfc3339(),
+        "edited_timestamp": null,
+        "pinned": false,
+        "type": 0,
+        "author": user_json(author),
+        "mention_everyone": false,
+        "mentions": mentions,
+        "mention_roles": [],
+        "attachments": [],
+        "embeds": []
+    })).expect("Mock message doesn't match the discord model")
+}
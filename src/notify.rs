@@ -0,0 +1,85 @@
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// What's allowed to notify in a server or channel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Level {
+    All,
+    Mentions, // mentions of us, our roles or everyone, and DMs
+    Nothing,
+}
+
+impl Level {
+    pub fn parse(value: &str) -> Result<Level, String> {
+        match value {
+            "all" => Ok(Level::All),
+            "mentions" => Ok(Level::Mentions),
+            "none" => Ok(Level::Nothing),
+            _ => Err(format!("`{}` should be all, mentions or none", value)),
+        }
+    }
+}
+
+/// What a notification is about
+pub struct Alert<'a> {
+    pub author: &'a str,
+    pub place: &'a str,
+    pub content: &'a str,
+}
+
+/// Rings the bell and puts the message in the window title, which terminals
+/// show even when they're in the background
+pub fn ring(alert: &Alert) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    write!(stdout, "\x07\x1b]2;* {} in {}\x07", printable(alert.author), printable(alert.place))?;
+    stdout.flush()
+}
+
+/// Pushes the terminal's current title onto its title stack, before we write
+/// our own. Terminals without one just ignore this.
+pub fn save_title() -> io::Result<()> {
+    let mut stdout = io::stdout();
+    write!(stdout, "\x1b[22;0t")?;
+    stdout.flush()
+}
+
+/// Back to the title we saved, keeping a copy on the stack for the next alert
+pub fn reset_title() -> io::Result<()> {
+    let mut stdout = io::stdout();
+    write!(stdout, "\x1b[23;0t\x1b[22;0t")?;
+    stdout.flush()
+}
+
+/// Back to the title we saved, for good
+pub fn restore_title() -> io::Result<()> {
+    let mut stdout = io::stdout();
+    write!(stdout, "\x1b[23;0t")?;
+    stdout.flush()
+}
+
+/// Runs the configured command through the shell, with the author, channel and
+/// content both as $1, $2 and $3 and as DISCORD_AUTHOR, DISCORD_CHANNEL and DISCORD_CONTENT
+pub fn run_command(command: &str, alert: &Alert) -> io::Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .arg("discord_term")
+        .args(&[alert.author, alert.place, alert.content])
+        .env("DISCORD_AUTHOR", alert.author)
+        .env("DISCORD_CHANNEL", alert.place)
+        .env("DISCORD_CONTENT", alert.content)
+        // Anything it prints would scribble over the UI
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // Wait for it elsewhere so it doesn't linger as a zombie
+    thread::spawn(move || child.wait());
+    Ok(())
+}
+
+/// Control characters would end the title's escape sequence early
+fn printable(text: &str) -> String {
+    text.chars().filter(|c| !c.is_control()).collect()
+}
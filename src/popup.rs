@@ -0,0 +1,71 @@
+use tui::layout::Rect;
+use tui::widgets::{Widget, Block, Borders};
+use tui::buffer::Buffer;
+use tui::style::{Color, Style};
+
+use std::cmp::min;
+
+/// A bordered box of lines drawn over whatever is already on screen
+pub struct Popup<'a> {
+    title: &'a str,
+    lines: &'a [String],
+    selected: Option<usize>,
+}
+
+impl<'a> Popup<'a> {
+    pub fn new(title: &'a str, lines: &'a [String]) -> Self {
+        Popup {
+            title,
+            lines,
+            selected: None,
+        }
+    }
+    pub fn select(&mut self, selected: Option<usize>) -> &mut Self {
+        self.selected = selected;
+        self
+    }
+}
+
+impl<'a> Widget for Popup<'a> {
+    fn draw(&mut self, area: &Rect, buf: &mut Buffer) {
+        // Blank out what's underneath, borders included
+        let blank = " ".repeat(area.width as usize);
+        for y in area.top()..area.bottom() {
+            buf.set_stringn(area.left(), y, &blank, area.width as usize, &Style::default());
+        }
+
+        let mut block = Block::default()
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(Color::Green))
+            .title(self.title);
+        block.draw(area, buf);
+        let inner = block.inner(area);
+
+        // Keep the selected line in view
+        let height = inner.height as usize;
+        let first = match self.selected {
+            Some(selected) if selected >= height => selected + 1 - height,
+            _ => 0,
+        };
+        for (row, line) in self.lines.iter().skip(first).take(height).enumerate() {
+            let style = if self.selected == Some(first + row) {
+                Style::default().fg(Color::Black).bg(Color::Green)
+            } else {
+                Style::default()
+            };
+            buf.set_stringn(inner.left(), inner.top() + row as u16, line, inner.width as usize, &style);
+        }
+    }
+}
+
+/// A rect of at most `width` x `height`, centered in `area`
+pub fn centered(area: &Rect, width: u16, height: u16) -> Rect {
+    let width = min(width, area.width);
+    let height = min(height, area.height);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
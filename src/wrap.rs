@@ -0,0 +1,150 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use tui::style::Style;
+
+use markdown::{Line, Span};
+
+/// Display width of a string in terminal columns
+pub fn width(text: &str) -> usize {
+    UnicodeWidthStr::width(text)
+}
+
+/// A run of graphemes that wraps as a unit: a word, or the whitespace between words
+struct Word<'a> {
+    graphemes: Vec<(&'a str, Style)>,
+    width: usize,
+    space: bool,
+}
+
+fn words(line: &Line) -> Vec<Word> {
+    let mut words: Vec<Word> = vec![];
+    for span in line.spans.iter() {
+        for grapheme in UnicodeSegmentation::graphemes(&span.text[..], true) {
+            let space = grapheme.chars().all(char::is_whitespace);
+            let continues = words.last().map_or(false, |word| word.space == space);
+            if !continues {
+                words.push(Word { graphemes: vec![], width: 0, space });
+            }
+            let word = words.last_mut().unwrap();
+            word.graphemes.push((grapheme, span.style));
+            word.width += width(grapheme);
+        }
+    }
+    words
+}
+
+/// Builds one row, merging neighbouring graphemes that share a style
+struct Row {
+    line: Line,
+    column: usize,
+    start: usize,
+}
+
+impl Row {
+    fn new(indent: usize, code: bool) -> Self {
+        let mut spans = vec![];
+        if indent > 0 {
+            spans.push(Span { text: " ".repeat(indent), style: Style::default() });
+        }
+        Row {
+            line: Line { spans, code },
+            column: indent,
+            start: indent,
+        }
+    }
+    fn is_empty(&self) -> bool {
+        self.column == self.start
+    }
+    fn push(&mut self, grapheme: &str, style: Style) {
+        let same_style = self.line.spans.last().map_or(false, |last| last.style == style);
+        if !same_style {
+            self.line.spans.push(Span { text: String::new(), style });
+        }
+        self.line.spans.last_mut().unwrap().text.push_str(grapheme);
+        self.column += width(grapheme);
+    }
+}
+
+/// Wraps lines to `max_width` columns, breaking between words where it can and
+/// mid-word only when a word is wider than a whole row. Every row but the very
+/// first is indented by `indent` columns.
+pub fn wrap(lines: Vec<Line>, max_width: usize, indent: usize) -> Vec<Line> {
+    let indent = if indent < max_width { indent } else { 0 };
+    let mut rows = vec![];
+
+    for (i, line) in lines.iter().enumerate() {
+        let mut row = Row::new(if i == 0 { 0 } else { indent }, line.code);
+        let mut wrapped = false;
+
+        for word in words(line) {
+            if row.column + word.width <= max_width {
+                for &(grapheme, style) in word.graphemes.iter() {
+                    row.push(grapheme, style);
+                }
+                continue;
+            }
+            // Doesn't fit. Break here, dropping whitespace at the break
+            if word.space || !row.is_empty() {
+                rows.push(row.line);
+                row = Row::new(indent, line.code);
+                wrapped = true;
+            }
+            if word.space {
+                continue;
+            }
+            // Only words wider than a whole row get split
+            for &(grapheme, style) in word.graphemes.iter() {
+                // Always place at least one grapheme per row, or we'd never finish
+                if row.column + width(grapheme) > max_width && !row.is_empty() {
+                    rows.push(row.line);
+                    row = Row::new(indent, line.code);
+                }
+                row.push(grapheme, style);
+            }
+        }
+        // Trailing whitespace that wrapped shouldn't leave a blank row behind
+        if !(wrapped && row.is_empty()) {
+            rows.push(row.line);
+        }
+    }
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(text: &str) -> Line {
+        Line { spans: vec![Span { text: String::from(text), style: Style::default() }], code: false }
+    }
+
+    fn texts(lines: &[Line]) -> Vec<String> {
+        lines.iter().map(|line| line.spans.iter().map(|span| &span.text[..]).collect()).collect()
+    }
+
+    #[test]
+    fn wide_graphemes_are_not_split_across_rows() {
+        let rows = wrap(vec![line("日本語日本語")], 5, 0);
+        assert_eq!(texts(&rows), vec!["日本", "語日", "本語"]);
+        assert!(texts(&rows).iter().all(|row| width(row) <= 5));
+    }
+
+    #[test]
+    fn a_row_too_narrow_for_a_grapheme_still_gets_one() {
+        let rows = wrap(vec![line("日本")], 1, 0);
+        assert_eq!(texts(&rows), vec!["日", "本"]);
+    }
+
+    #[test]
+    fn continuation_rows_are_indented() {
+        let rows = wrap(vec![line("日本語")], 4, 1);
+        assert_eq!(texts(&rows), vec!["日本", " 語"]);
+    }
+
+    #[test]
+    fn combining_marks_stay_with_their_letter() {
+        let rows = wrap(vec![line("e\u{301}e\u{301}e\u{301}")], 2, 0);
+        assert_eq!(texts(&rows), vec!["e\u{301}e\u{301}", "e\u{301}"]);
+    }
+}